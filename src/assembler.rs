@@ -0,0 +1,733 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::disasm::PROG_START;
+use crate::file_io::write_program;
+
+/// Macro expansions allowed per program, guards against recursive macros
+const MAX_EXPANSIONS: usize = 10000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.msg
+        )
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, msg: impl Into<String>) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column,
+            msg: msg.into(),
+        }
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum Fixup {
+    /// Low 12 bits of the opcode at the offset
+    Addr12,
+    /// Whole word at the offset, for `i := long`
+    Addr16,
+}
+
+/// Octo-compatible subset of CHIP-8 assembly
+struct Assembler {
+    tokens: Vec<Token>,
+    pos: usize,
+    out: Vec<u8>,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, u16>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, Fixup, Token)>,
+    /// Open `loop`s with their start address and the `while` exits to patch
+    loops: Vec<(Token, u16, Vec<usize>)>,
+    expansions: usize,
+}
+
+/// Assemble Octo source into a program loaded at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler {
+        tokens: tokenize(source),
+        pos: 0,
+        out: Vec::new(),
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        loops: Vec::new(),
+        expansions: 0,
+    };
+
+    // Execution starts at 0x200, so enter `main` if something comes first
+    let main = asm
+        .tokens
+        .windows(2)
+        .position(|w| w[0].text == ":" && w[1].text == "main");
+    if let Some(i) = main.filter(|i| *i > 0) {
+        let tok = asm.tokens[i + 1].clone();
+        asm.emit_addr(0x1000, &tok)?;
+    }
+
+    while asm.pos < asm.tokens.len() {
+        asm.statement()?;
+    }
+    if let Some((tok, _, _)) = asm.loops.last() {
+        return Err(tok.error("'loop' without 'again'"));
+    }
+
+    asm.resolve()?;
+    Ok(asm.out)
+}
+
+/// Assemble a source file, writing the program next to it unless `output` is given
+pub fn run(source: &str, output: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let prog =
+        assemble(&fs::read_to_string(source)?).map_err(|err| format!("{}: {}", source, err))?;
+
+    let output = match output {
+        Some(fname) => fname.to_string(),
+        None => Path::new(source)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    write_program(&output, &prog)?;
+    println!("Wrote {} bytes to {}", prog.len(), output);
+
+    Ok(())
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (lineno, line) in source.lines().enumerate() {
+        let mut start: Option<usize> = None;
+        let chars: Vec<char> = line.chars().collect();
+        for i in 0..=chars.len() {
+            let c = chars.get(i).copied();
+            let boundary = c.is_none_or(|c| c.is_whitespace() || (c == '#' && start.is_none()));
+            if boundary {
+                if let Some(s) = start.take() {
+                    tokens.push(Token {
+                        text: chars[s..i].iter().collect(),
+                        line: lineno + 1,
+                        column: s + 1,
+                    });
+                }
+                if c == Some('#') {
+                    break;
+                }
+            } else if start.is_none() {
+                start = Some(i);
+            }
+        }
+    }
+
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (neg, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let val = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+
+    Some(if neg { -val } else { val })
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+impl Assembler {
+    fn addr(&self) -> u16 {
+        PROG_START + self.out.len() as u16
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.get(self.pos) {
+            Some(tok) => {
+                self.pos += 1;
+                Ok(tok.clone())
+            }
+            None => {
+                let last = self.tokens.last().unwrap();
+                Err(AsmError {
+                    line: last.line,
+                    column: last.column + last.text.len(),
+                    msg: String::from("unexpected end of input"),
+                })
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+        let tok = self.next()?;
+        if tok.text != text {
+            return Err(tok.error(format!("expected '{}', found '{}'", text, tok.text)));
+        }
+        Ok(tok)
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        self.out.extend_from_slice(&opcode.to_be_bytes());
+    }
+
+    /// Emit `opcode` with a 12 bit address, resolved later if it is a label
+    fn emit_addr(&mut self, opcode: u16, tok: &Token) -> Result<(), AsmError> {
+        match self.constant(tok)? {
+            Some(n) if !(0..=0xFFF).contains(&n) => {
+                return Err(tok.error(format!("address {:#X} out of range", n)))
+            }
+            Some(n) => self.emit(opcode | n as u16),
+            None => {
+                self.fixups
+                    .push((self.out.len(), Fixup::Addr12, tok.clone()));
+                self.emit(opcode);
+            }
+        }
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<Token, AsmError> {
+        let tok = self.next()?;
+        if !is_identifier(&tok.text) {
+            return Err(tok.error(format!("invalid name '{}'", tok.text)));
+        }
+        Ok(tok)
+    }
+
+    /// Value of a number or `:const`, `None` for names that may be labels
+    fn constant(&self, tok: &Token) -> Result<Option<i64>, AsmError> {
+        if let Some(n) = parse_number(&tok.text) {
+            return Ok(Some(n));
+        }
+        if let Some(n) = self.consts.get(&tok.text) {
+            return Ok(Some(*n as i64));
+        }
+        if let Some(n) = self.labels.get(&tok.text) {
+            return Ok(Some(*n as i64));
+        }
+        if !is_identifier(&tok.text) {
+            return Err(tok.error(format!("expected a value, found '{}'", tok.text)));
+        }
+        Ok(None)
+    }
+
+    fn value(&mut self, max: i64) -> Result<u16, AsmError> {
+        let tok = self.next()?;
+        match self.constant(&tok)? {
+            Some(n) if n < -(max + 1) / 2 || n > max => {
+                Err(tok.error(format!("value {} out of range", n)))
+            }
+            Some(n) => Ok((n as u16) & (max as u16)),
+            None => Err(tok.error(format!("undefined constant '{}'", tok.text))),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u16, AsmError> {
+        self.value(0xFF)
+    }
+
+    fn nibble(&mut self) -> Result<u16, AsmError> {
+        let tok = self.next()?;
+        match self.constant(&tok)? {
+            Some(n) if (0..=0xF).contains(&n) => Ok(n as u16),
+            _ => Err(tok.error(format!(
+                "expected a value from 0 to 15, found '{}'",
+                tok.text
+            ))),
+        }
+    }
+
+    fn register_of(&self, text: &str) -> Option<u8> {
+        if let Some(x) = self.aliases.get(text) {
+            return Some(*x);
+        }
+        let mut chars = text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v' | 'V'), Some(c), None) => c.to_digit(16).map(|x| x as u8),
+            _ => None,
+        }
+    }
+
+    fn register(&mut self) -> Result<u16, AsmError> {
+        let tok = self.next()?;
+        self.register_of(&tok.text)
+            .map(|x| x as u16)
+            .ok_or_else(|| tok.error(format!("expected a register, found '{}'", tok.text)))
+    }
+
+    /// Opcode that skips the next instruction when the condition holds
+    fn condition(&mut self) -> Result<u16, AsmError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        match op.text.as_str() {
+            "key" => Ok(0xE09E | (x << 8)),
+            "-key" => Ok(0xE0A1 | (x << 8)),
+            "==" | "!=" => {
+                let eq = op.text == "==";
+                match self.peek().and_then(|t| self.register_of(t)) {
+                    Some(y) => {
+                        self.pos += 1;
+                        let base = if eq { 0x5000 } else { 0x9000 };
+                        Ok(base | (x << 8) | ((y as u16) << 4))
+                    }
+                    None => {
+                        let base = if eq { 0x3000 } else { 0x4000 };
+                        Ok(base | (x << 8) | self.byte()?)
+                    }
+                }
+            }
+            _ => Err(op.error(format!("unsupported comparison '{}'", op.text))),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let tok = self.next()?;
+
+        match tok.text.as_str() {
+            ":" => {
+                let name = self.ident()?;
+                if self.labels.contains_key(&name.text) {
+                    return Err(name.error(format!("label '{}' already defined", name.text)));
+                }
+                self.labels.insert(name.text, self.addr());
+            }
+            ":const" => {
+                let name = self.ident()?;
+                let val = self.value(0xFFFF)?;
+                self.consts.insert(name.text, val);
+            }
+            ":alias" => {
+                let name = self.ident()?;
+                let x = self.register()?;
+                self.aliases.insert(name.text, x as u8);
+            }
+            ":macro" => self.define_macro()?,
+            ":call" => {
+                let target = self.next()?;
+                self.emit_addr(0x2000, &target)?;
+            }
+            "clear" => self.emit(0x00E0),
+            "return" => self.emit(0x00EE),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "audio" => self.emit(0xF002),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n);
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | (n << 8));
+            }
+            "jump" => {
+                let target = self.next()?;
+                self.emit_addr(0x1000, &target)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_addr(0xB000, &target)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | (x << 8) | (y << 4) | n);
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | (x << 8));
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(0xF075 | (x << 8));
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(0xF085 | (x << 8));
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let save = tok.text == "save";
+                if self.peek() == Some("-") {
+                    self.pos += 1;
+                    let y = self.register()?;
+                    let base = if save { 0x5002 } else { 0x5003 };
+                    self.emit(base | (x << 8) | (y << 4));
+                } else {
+                    let base = if save { 0xF055 } else { 0xF065 };
+                    self.emit(base | (x << 8));
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let base = match tok.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(base | (x << 8));
+            }
+            "i" => self.i_statement()?,
+            "if" => {
+                // `if` runs the next statement when the condition holds, so
+                // the emitted skip tests the opposite
+                let skip = self.condition()?;
+                self.expect("then")?;
+                let inverse = match skip & 0xF000 {
+                    0x3000 => skip + 0x1000,
+                    0x4000 => skip - 0x1000,
+                    0x5000 => skip + 0x4000,
+                    0x9000 => skip - 0x4000,
+                    _ => skip ^ 0x003F, // EX9E <-> EXA1
+                };
+                self.emit(inverse);
+            }
+            "loop" => self.loops.push((tok, self.addr(), Vec::new())),
+            "while" => {
+                let skip = self.condition()?;
+                let exits = match self.loops.last_mut() {
+                    Some((_, _, exits)) => exits,
+                    None => return Err(tok.error("'while' outside of a loop")),
+                };
+                exits.push(self.out.len() + 2);
+                self.emit(skip);
+                self.emit(0x1000);
+            }
+            "again" => {
+                let (_, start, exits) = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| tok.error("'again' without 'loop'"))?;
+                self.emit(0x1000 | start);
+                let end = self.addr();
+                for offset in exits {
+                    self.out[offset..(offset + 2)].copy_from_slice(&(0x1000 | end).to_be_bytes());
+                }
+            }
+            _ => {
+                if let Some(x) = self.register_of(&tok.text) {
+                    return self.register_statement(x as u16);
+                }
+                if let Some(n) = parse_number(&tok.text) {
+                    if !(-128..=255).contains(&n) {
+                        return Err(tok.error(format!("byte {} out of range", n)));
+                    }
+                    self.out.push(n as u8);
+                    return Ok(());
+                }
+                if self.macros.contains_key(&tok.text) {
+                    return self.expand_macro(&tok);
+                }
+                if !is_identifier(&tok.text) {
+                    return Err(tok.error(format!("unexpected '{}'", tok.text)));
+                }
+                // A bare name calls the subroutine of that name
+                self.emit_addr(0x2000, &tok)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn i_statement(&mut self) -> Result<(), AsmError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.emit(0xF01E | (x << 8));
+            }
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let big = self.next()?.text == "bighex";
+                    let x = self.register()?;
+                    self.emit(if big { 0xF030 } else { 0xF029 } | (x << 8));
+                }
+                Some("long") => {
+                    self.pos += 1;
+                    let target = self.next()?;
+                    self.emit(0xF000);
+                    match self.constant(&target)? {
+                        Some(n) if (0..=0xFFFF).contains(&n) => self.emit(n as u16),
+                        Some(n) => {
+                            return Err(target.error(format!("address {:#X} out of range", n)))
+                        }
+                        None => {
+                            self.fixups.push((self.out.len(), Fixup::Addr16, target));
+                            self.emit(0);
+                        }
+                    }
+                }
+                _ => {
+                    let target = self.next()?;
+                    self.emit_addr(0xA000, &target)?;
+                }
+            },
+            _ => return Err(op.error(format!("expected ':=' or '+=', found '{}'", op.text))),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u16) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let y = self
+            .peek()
+            .and_then(|t| self.register_of(t))
+            .map(|y| (y as u16) << 4);
+        if y.is_some() {
+            self.pos += 1;
+        }
+
+        let opcode = match (op.text.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | y,
+            ("|=", Some(y)) => 0x8001 | y,
+            ("&=", Some(y)) => 0x8002 | y,
+            ("^=", Some(y)) => 0x8003 | y,
+            ("+=", Some(y)) => 0x8004 | y,
+            ("-=", Some(y)) => 0x8005 | y,
+            (">>=", Some(y)) => 0x8006 | y,
+            ("=-", Some(y)) => 0x8007 | y,
+            ("<<=", Some(y)) => 0x800E | y,
+            (":=", None) => match self.peek() {
+                Some("random") => {
+                    self.pos += 1;
+                    0xC000 | self.byte()?
+                }
+                Some("delay") => {
+                    self.pos += 1;
+                    0xF007
+                }
+                Some("key") => {
+                    self.pos += 1;
+                    0xF00A
+                }
+                _ => 0x6000 | self.byte()?,
+            },
+            ("+=", None) => 0x7000 | self.byte()?,
+            ("-=", None) => 0x7000 | (self.byte()?.wrapping_neg() & 0xFF),
+            _ => return Err(op.error(format!("unsupported operator '{}'", op.text))),
+        };
+
+        self.emit(opcode | (x << 8));
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.ident()?;
+        let mut params = Vec::new();
+        loop {
+            let tok = self.next()?;
+            if tok.text == "{" {
+                break;
+            }
+            if !is_identifier(&tok.text) {
+                return Err(tok.error(format!("invalid macro parameter '{}'", tok.text)));
+            }
+            params.push(tok.text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let tok = self.next()?;
+            match tok.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(tok);
+        }
+
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    /// Replace the macro invocation with its body, arguments substituted
+    fn expand_macro(&mut self, tok: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(tok.error("too many macro expansions"));
+        }
+
+        let mac = &self.macros[&tok.text];
+        let nargs = mac.params.len();
+        if self.pos + nargs > self.tokens.len() {
+            return Err(tok.error(format!("macro '{}' takes {} arguments", tok.text, nargs)));
+        }
+        let args = &self.tokens[self.pos..(self.pos + nargs)];
+        let body: Vec<Token> = mac
+            .body
+            .iter()
+            .map(|t| match mac.params.iter().position(|p| *p == t.text) {
+                Some(i) => Token {
+                    text: args[i].text.clone(),
+                    ..t.clone()
+                },
+                None => t.clone(),
+            })
+            .collect();
+
+        self.tokens.splice(self.pos..(self.pos + nargs), body);
+        Ok(())
+    }
+
+    fn resolve(&mut self) -> Result<(), AsmError> {
+        for (offset, kind, tok) in self.fixups.iter() {
+            let addr = *self
+                .labels
+                .get(&tok.text)
+                .ok_or_else(|| tok.error(format!("undefined label '{}'", tok.text)))?;
+            let word = u16::from_be_bytes([self.out[*offset], self.out[offset + 1]]);
+            let word = match kind {
+                Fixup::Addr12 if addr > 0xFFF => {
+                    return Err(tok.error(format!("label '{}' is past 0xFFF", tok.text)))
+                }
+                Fixup::Addr12 => word | addr,
+                Fixup::Addr16 => addr,
+            };
+            self.out[*offset..(offset + 2)].copy_from_slice(&word.to_be_bytes());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disasm::disassemble;
+    use crate::emulator::Emulator;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            : main
+                clear
+                v0 := 0   # counter
+                i := digit
+                loop
+                    v0 += 1
+                    if v0 == 5 then jump done
+                again
+            : done
+                sprite v0 v1 5
+                exit
+            : digit
+                0xF0 0x90 0b11110000
+        ";
+        let prog = assemble(source).unwrap();
+        let listing: Vec<String> = disassemble(&prog[..18], PROG_START)
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            listing,
+            vec![
+                "CLS",
+                "LD V0, 0x00",
+                "LD I, 0x212",
+                "ADD V0, 0x01",
+                "SNE V0, 0x05",
+                "JP 0x20E",
+                "JP 0x206",
+                "DRW V0, V1, 5",
+                "EXIT"
+            ]
+        );
+        assert_eq!(&prog[18..], &[0xF0, 0x90, 0xF0]);
+
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&prog).unwrap();
+        while ch8.step(0).unwrap() != crate::emulator::StepOutcome::Exited {}
+        assert_eq!(ch8.get_state().register_bank[0], 5);
+    }
+
+    #[test]
+    fn test_macros_and_main() {
+        let source = "
+            :macro add-twice reg n { reg += n reg += n }
+            : helper
+                return
+            : main
+                add-twice v3 2
+                helper
+        ";
+        let prog = assemble(source).unwrap();
+        assert_eq!(
+            prog,
+            vec![0x12, 0x04, 0x00, 0xEE, 0x73, 0x02, 0x73, 0x02, 0x22, 0x02]
+        );
+
+        let prog = assemble("if v1 key then v2 += 1\nif v1 -key then v2 += 1").unwrap();
+        assert_eq!(prog, vec![0xE1, 0xA1, 0x72, 0x01, 0xE1, 0x9E, 0x72, 0x01]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = assemble("clear\n  v0 := 300\n").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 9));
+
+        let err = assemble("jump nowhere").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 6));
+        assert_eq!(err.msg, "undefined label 'nowhere'");
+
+        let err = assemble("loop\n v0 += 1").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 1));
+    }
+}
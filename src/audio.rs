@@ -0,0 +1,294 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, stdout, BufWriter, Seek, SeekFrom, Write};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::EmulatorState;
+use crate::timing::FRAME_RATE;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AudioBackend {
+    /// Ring the terminal bell when the buzzer starts
+    #[default]
+    Bell,
+    /// Record the buzzer to the WAV file given by --audio-file
+    Wav,
+    /// No sound
+    None,
+}
+
+/// Where the buzzer goes, fed once per frame that the emulator ran
+pub trait AudioSink {
+    /// Called before the timers tick, so a sound timer of 1 still sounds
+    fn frame(&mut self, state: &EmulatorState);
+
+    /// Write out anything still buffered, at the end of the session
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+impl AudioBackend {
+    pub fn open(self, file: Option<&str>) -> Result<Box<dyn AudioSink>, Box<dyn Error>> {
+        Ok(match self {
+            AudioBackend::Bell => Box::new(BellSink::new(stdout())),
+            AudioBackend::Wav => {
+                let fname = file.ok_or("--audio wav needs --audio-file")?;
+                Box::new(WavSink::new(BufWriter::new(File::create(fname)?))?)
+            }
+            AudioBackend::None => Box::new(NullSink),
+        })
+    }
+}
+
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn frame(&mut self, _state: &EmulatorState) {}
+}
+
+/// Sends BEL when the sound timer becomes nonzero. Terminals only beep
+/// for a fixed time, so this can't follow the length of the sound.
+pub struct BellSink<W: Write> {
+    out: W,
+    on: bool,
+}
+
+impl<W: Write> BellSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, on: false }
+    }
+}
+
+impl<W: Write> AudioSink for BellSink<W> {
+    fn frame(&mut self, state: &EmulatorState) {
+        let on = state.sound_timer > 0;
+        if on && !self.on {
+            // Losing a beep isn't worth stopping the emulator for
+            let _ = self.out.write_all(b"\x07").and_then(|_| self.out.flush());
+        }
+        self.on = on;
+    }
+}
+
+const SAMPLE_RATE: u32 = 44100;
+const AMPLITUDE: i16 = 8000;
+const WAV_HEADER_LEN: u32 = 44;
+
+/// 440 Hz square wave for programs that never load an XO-CHIP pattern,
+/// as a 2 bit pattern played at 880 bits per second
+const SQUARE: [u8; 1] = [0x80];
+const SQUARE_RATE: f64 = 880.;
+
+/// 16 bit mono PCM of the buzzer. Programs that load an XO-CHIP audio
+/// pattern play it at the rate set by the pitch register.
+pub struct WavSink<W: Write + Seek> {
+    out: W,
+    samples: u32,
+    /// Position in the pattern in bits
+    phase: f64,
+    /// First write error, no more samples are written after it
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut out: W) -> Result<Self, Box<dyn Error>> {
+        // Sizes are filled in by `finish`
+        write_wav_header(&mut out, 0)?;
+        Ok(Self {
+            out,
+            samples: 0,
+            phase: 0.,
+            error: None,
+        })
+    }
+
+    fn write_frame(&mut self, state: &EmulatorState) -> io::Result<()> {
+        let xo = state.audio_loaded;
+        let (pattern, rate) = match xo {
+            true => (
+                &state.audio_pattern[..],
+                4000. * 2f64.powf((state.pitch as f64 - 64.) / 48.),
+            ),
+            false => (&SQUARE[..], SQUARE_RATE),
+        };
+        let bits = match xo {
+            true => pattern.len() * 8,
+            false => 2,
+        };
+
+        let on = state.sound_timer > 0;
+        let samples = (SAMPLE_RATE as f64 / FRAME_RATE) as u32;
+        for _ in 0..samples {
+            let bit = self.phase as usize % bits;
+            let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            let sample = match (on, high) {
+                (false, _) => 0,
+                (true, true) => AMPLITUDE,
+                (true, false) => -AMPLITUDE,
+            };
+            self.out.write_all(&sample.to_le_bytes())?;
+            self.phase = (self.phase + rate / SAMPLE_RATE as f64) % bits as f64;
+        }
+        self.samples += samples;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn frame(&mut self, state: &EmulatorState) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.write_frame(state) {
+            self.error = Some(err);
+        }
+    }
+
+    /// Fill in the header sizes, reporting the first write error if any
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(err) = self.error.take() {
+            return Err(err.into());
+        }
+        self.out.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.out, self.samples * 2)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn write_wav_header<W: Write>(out: &mut W, data_len: u32) -> io::Result<()> {
+    out.write_all(b"RIFF")?;
+    out.write_all(&(WAV_HEADER_LEN - 8 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM, mono
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    // Byte rate and block align for 16 bit samples
+    out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::quirks::Quirks;
+    use std::io::Cursor;
+
+    fn state(sound_timer: u8) -> EmulatorState {
+        let mut state = Emulator::new(Quirks::default()).get_state().clone();
+        state.sound_timer = sound_timer;
+        state
+    }
+
+    #[test]
+    fn test_bell() {
+        let mut out = Vec::new();
+        let mut sink = BellSink::new(&mut out);
+        for timer in [0, 3, 2, 1, 0, 5] {
+            sink.frame(&state(timer));
+        }
+        assert_eq!(out, b"\x07\x07");
+    }
+
+    #[test]
+    fn test_wav() {
+        let mut out = Cursor::new(Vec::new());
+        let mut sink = WavSink::new(&mut out).unwrap();
+        sink.frame(&state(1));
+        sink.frame(&state(0));
+        sink.finish().unwrap();
+
+        let wav = out.into_inner();
+        let samples: Vec<i16> = wav[WAV_HEADER_LEN as usize..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples.len(), 2 * 735);
+        assert_eq!(&wav[4..8], &(36 + 4 * 735u32).to_le_bytes());
+        assert_eq!(&wav[40..44], &(4 * 735u32).to_le_bytes());
+
+        // Half a period of 440 Hz is just over 50 samples
+        assert!(samples[..50].iter().all(|s| *s == AMPLITUDE));
+        assert!(samples[51..100].iter().all(|s| *s == -AMPLITUDE));
+        assert!(samples[735..].iter().all(|s| *s == 0));
+    }
+
+    /// Accepts a fixed number of bytes, like a full disk
+    struct Full(Cursor<Vec<u8>>, usize);
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0.position() as usize + buf.len() > self.1 {
+                return Err(io::Error::other("disk full"));
+            }
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Full {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_wav_error() {
+        let mut sink = WavSink::new(Full(Cursor::new(Vec::new()), 1000)).unwrap();
+        sink.frame(&state(1));
+        assert_eq!(sink.samples, 0);
+        sink.frame(&state(1));
+        assert!(sink.finish().is_err());
+    }
+
+    #[test]
+    fn test_wav_silent_pattern() {
+        let mut out = Cursor::new(Vec::new());
+        let mut sink = WavSink::new(&mut out).unwrap();
+        let mut state = state(1);
+        state.audio_loaded = true;
+        sink.frame(&state);
+        sink.finish().unwrap();
+
+        // A flat line rather than the 440 Hz fallback
+        let wav = out.into_inner();
+        let data = &wav[WAV_HEADER_LEN as usize..];
+        assert!(data.chunks(2).all(|b| b == &data[..2]));
+    }
+
+    #[test]
+    fn test_wav_pattern() {
+        let mut out = Cursor::new(Vec::new());
+        let mut sink = WavSink::new(&mut out).unwrap();
+        let mut state = state(1);
+        // Alternating bits at 4000 bits per second
+        state.audio_pattern = vec![0xAA; 16];
+        state.audio_loaded = true;
+        sink.frame(&state);
+        sink.finish().unwrap();
+
+        let wav = out.into_inner();
+        let first: Vec<i16> = wav[WAV_HEADER_LEN as usize..][..40]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        // Each bit lasts 44100 / 4000 = 11 samples
+        assert!(first[..11].iter().all(|s| *s == AMPLITUDE));
+        assert!(first[12..19].iter().all(|s| *s == -AMPLITUDE));
+    }
+}
@@ -1,12 +1,491 @@
-use clap::Parser;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use crate::audio::AudioBackend;
+use crate::debugger::{Breakpoint, Condition, Watchpoint};
+use crate::disasm::Syntax;
+use crate::file_io::read_program;
+use crate::headless::DumpFormat;
+use crate::input::InputBackend;
+use crate::quirks::QuirksPreset;
+use crate::render::RenderMode;
+use crate::romdb::{RomDb, RomInfo};
+use crate::theme::ThemePreset;
+use crate::timing::FRAME_RATE;
+use crate::trace::{AddrRange, OpClass, TraceFilter};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Config {
-    /// Optional name to operate on
-    #[arg(short, long)]
-    pub frequency: Option<f32>,
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    /// Config file with defaults for the flags below, instead of
+    /// ~/.config/chip-8/config.toml
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<String>,
+
+    /// Config file the defaults were read from
+    #[arg(skip)]
+    pub config_source: Option<String>,
+
+    /// ROM database in the chip-8-database `programs.json` format, instead
+    /// of ~/.config/chip-8/programs.json
+    #[arg(long, value_name = "FILE")]
+    pub rom_db: Option<String>,
+
+    /// Database entry for the program, its quirks, speed and keys are used
+    /// unless given as flags
+    #[arg(skip)]
+    pub rom: Option<RomInfo>,
+
+    /// Instructions executed per second
+    #[arg(short, long, conflicts_with = "ipf")]
+    pub frequency: Option<f64>,
+
+    /// Instructions executed per 60 Hz frame, an alternative to --frequency
+    #[arg(long)]
+    pub ipf: Option<u32>,
 
     #[arg(short, long)]
     pub program: Option<String>,
+
+    /// Interpreter behaviour to emulate for ambiguous opcodes
+    #[arg(short, long, value_enum, default_value_t = QuirksPreset::Default)]
+    pub quirks: QuirksPreset,
+
+    /// Seed for the CXNN random number generator, random when not given
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Save state to restore after loading the program
+    #[arg(short, long)]
+    pub load_state: Option<String>,
+
+    /// How pixels are drawn in the terminal
+    #[arg(long, value_enum, default_value_t = RenderMode::Block)]
+    pub render: RenderMode,
+
+    /// Colours of the terminal interface
+    #[arg(long, value_enum, default_value_t = ThemePreset::Default)]
+    pub theme: ThemePreset,
+
+    /// TOML file with theme colours, missing ones come from --theme
+    #[arg(long, value_name = "FILE")]
+    pub theme_file: Option<String>,
+
+    /// Frames a pixel takes to fade out after turning off, to hide flicker
+    #[arg(long, value_name = "FRAMES", default_value_t = 0)]
+    pub persistence: u8,
+
+    /// Where keyboard input comes from
+    #[arg(long, value_enum, default_value_t = InputBackend::Device)]
+    pub keyboard: InputBackend,
+
+    /// Milliseconds a key press counts as held with `--keyboard terminal`
+    #[arg(long, value_name = "MS", default_value_t = 200)]
+    pub key_hold: u64,
+
+    /// TOML file binding keys to the keypad and emulator commands. A
+    /// `<program>.keymap.toml` next to the ROM is applied on top.
+    #[arg(long, value_name = "FILE")]
+    pub keymap: Option<String>,
+
+    /// Where the buzzer goes
+    #[arg(long, value_enum, default_value_t = AudioBackend::Bell)]
+    pub audio: AudioBackend,
+
+    /// WAV file to record the buzzer to with `--audio wav`
+    #[arg(long, value_name = "FILE")]
+    pub audio_file: Option<String>,
+
+    /// Directory for save slots, next to the program by default
+    #[arg(long, value_name = "DIR")]
+    pub save_dir: Option<String>,
+
+    /// Seconds of history kept for rewinding, 0 to disable
+    #[arg(long, value_name = "SECONDS", default_value_t = 10.)]
+    pub rewind: f64,
+
+    /// Start with execution paused
+    #[arg(long)]
+    pub paused: bool,
+
+    /// PC breakpoint as a hex address, optionally `<addr> if <condition>`
+    #[arg(short, long = "break", value_name = "BREAKPOINT")]
+    pub breakpoints: Vec<Breakpoint>,
+
+    /// Pause when a condition such as `V3 == 0x10 && I > 0x300` becomes true
+    #[arg(long, value_name = "CONDITION")]
+    pub break_when: Vec<Condition>,
+
+    /// Pause on access to `[r|w|rw:]<addr>[-<addr>]`, or on writes to `V0`-`VF` or `I`
+    #[arg(short, long)]
+    pub watch: Vec<Watchpoint>,
+
+    /// Record the keys held on each frame to a movie file
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["replay", "load_state", "headless"])]
+    pub record: Option<String>,
+
+    /// Play back a movie made with --record instead of reading the keyboard.
+    /// The seed, quirks and speed come from the movie.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["input", "load_state", "seed", "quirks", "frequency", "ipf", "frames"]
+    )]
+    pub replay: Option<String>,
+
+    /// Write a line per executed instruction to this file
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<String>,
+
+    /// Only trace instructions in a hex address range such as `200-2FF`
+    #[arg(long, value_name = "RANGE", requires = "trace")]
+    pub trace_range: Option<AddrRange>,
+
+    /// Only trace instructions of these classes
+    #[arg(long, value_enum, value_name = "CLASS", requires = "trace")]
+    pub trace_ops: Vec<OpClass>,
+
+    /// Run without the terminal interface and print the final state
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Number of 60 Hz frames to run in headless mode, the whole movie with --replay
+    #[arg(long, default_value_t = 600, requires = "headless")]
+    pub frames: u64,
+
+    /// Stop headless mode after this many instructions
+    #[arg(long, requires = "headless")]
+    pub cycles: Option<u64>,
+
+    /// Scripted input for headless mode, one `<frame> <keys...>` per line
+    #[arg(long, requires = "headless")]
+    pub input: Option<String>,
+
+    /// Format of the state dump printed by headless mode
+    #[arg(long, value_enum, default_value_t = DumpFormat::Text)]
+    pub format: DumpFormat,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Assemble Octo source into a ROM
+    Asm {
+        source: String,
+
+        /// Output file, defaults to the source path with a .ch8 extension
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Print a listing of a ROM
+    Disasm {
+        rom: String,
+
+        #[arg(short, long, value_enum, default_value_t = Syntax::Cowgod)]
+        syntax: Syntax,
+
+        /// Decode every word in order instead of following control flow
+        #[arg(long)]
+        linear: bool,
+    },
+    /// Find the first instruction where two --trace logs differ
+    Tracediff {
+        a: String,
+        b: String,
+
+        /// Lines to show before and after the divergence
+        #[arg(short, long, default_value_t = 5)]
+        context: usize,
+    },
+    /// Print the settings in effect after merging the config file and flags
+    Config,
+}
+
+/// Settings a config file can hold, named like their flags
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigFile {
+    pub frequency: Option<f64>,
+    pub ipf: Option<u32>,
+    pub quirks: Option<QuirksPreset>,
+    pub render: Option<RenderMode>,
+    pub theme: Option<ThemePreset>,
+    pub theme_file: Option<String>,
+    pub persistence: Option<u8>,
+    pub keyboard: Option<InputBackend>,
+    pub key_hold: Option<u64>,
+    pub keymap: Option<String>,
+    pub save_dir: Option<String>,
+    pub rewind: Option<f64>,
+    pub rom_db: Option<String>,
+    pub audio: Option<AudioBackend>,
+    pub audio_file: Option<String>,
+}
+
+impl ConfigFile {
+    /// Parse TOML such as
+    ///
+    /// ```toml
+    /// ipf = 15
+    /// quirks = "schip"
+    /// render = "half-block"
+    /// save-dir = "~/.local/share/chip-8"
+    /// ```
+    ///
+    /// Relative paths are taken from `dir`, the directory of the file.
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut file: ConfigFile = toml::from_str(text)?;
+        if file.frequency.is_some() && file.ipf.is_some() {
+            return Err("frequency and ipf can't both be set".into());
+        }
+        let paths = [
+            &mut file.theme_file,
+            &mut file.keymap,
+            &mut file.save_dir,
+            &mut file.rom_db,
+            &mut file.audio_file,
+        ];
+        for path in paths {
+            if let Some(path) = path.as_mut() {
+                *path = resolve_path(path, dir);
+            }
+        }
+        Ok(file)
+    }
+}
+
+/// Expand a leading `~/` and make relative paths relative to `dir`
+fn resolve_path(path: &str, dir: &Path) -> String {
+    let path = match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    };
+    dir.join(path).to_string_lossy().into_owned()
+}
+
+/// `$XDG_CONFIG_HOME/chip-8`, by default under `~/.config`
+fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("chip-8"))
+}
+
+pub fn default_config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+pub fn default_rom_db_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("programs.json"))
+}
+
+/// Take a setting from the config file unless its flag was given
+fn layer<T>(field: &mut T, value: Option<T>, from_cli: bool) {
+    if let (Some(value), false) = (value, from_cli) {
+        *field = value;
+    }
+}
+
+impl Config {
+    /// Parse the command line, taking anything it leaves out from the ROM
+    /// database entry of the program, then from the config file
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let matches = Config::command().get_matches();
+        let mut cfg = Config::from_arg_matches(&matches)?;
+        let path = match &cfg.config {
+            Some(fname) => Some(PathBuf::from(fname)),
+            None => default_config_path().filter(|path| path.exists()),
+        };
+        if let Some(path) = path {
+            let err = |err: Box<dyn Error>| format!("{}: {}", path.display(), err);
+            let text = fs::read_to_string(&path).map_err(|e| err(e.into()))?;
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let file = ConfigFile::parse(&text, dir).map_err(err)?;
+            cfg.layer(file, &matches);
+            cfg.config_source = Some(path.to_string_lossy().into_owned());
+        }
+
+        let db = match &cfg.rom_db {
+            Some(fname) => Some(fname.clone()),
+            None => default_rom_db_path()
+                .filter(|path| path.exists())
+                .map(|path| path.to_string_lossy().into_owned()),
+        };
+        if let (Some(db), Some(program)) = (db, &cfg.program) {
+            let rom = RomDb::load(&db)?.lookup(&read_program(program)?);
+            if let Some(rom) = rom {
+                cfg.layer_rom(rom, &matches);
+            }
+        }
+        Ok(cfg)
+    }
+
+    /// Apply the quirks and speed of a ROM database entry under the flags
+    /// given in `matches`
+    pub fn layer_rom(&mut self, rom: RomInfo, matches: &ArgMatches) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        if !from_cli("frequency") && !from_cli("ipf") && rom.ipf.is_some() {
+            self.frequency = None;
+            self.ipf = rom.ipf;
+        }
+        layer(&mut self.quirks, rom.quirks, from_cli("quirks"));
+        self.rom = Some(rom);
+    }
+
+    /// Apply config file settings under the flags given in `matches`
+    pub fn layer(&mut self, file: ConfigFile, matches: &ArgMatches) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        // Either speed flag replaces both speed settings
+        let speed_from_cli = from_cli("frequency") || from_cli("ipf");
+        layer(
+            &mut self.frequency,
+            file.frequency.map(Some),
+            speed_from_cli,
+        );
+        layer(&mut self.ipf, file.ipf.map(Some), speed_from_cli);
+        layer(&mut self.quirks, file.quirks, from_cli("quirks"));
+        layer(&mut self.render, file.render, from_cli("render"));
+        layer(&mut self.theme, file.theme, from_cli("theme"));
+        layer(
+            &mut self.theme_file,
+            file.theme_file.map(Some),
+            from_cli("theme_file"),
+        );
+        layer(
+            &mut self.persistence,
+            file.persistence,
+            from_cli("persistence"),
+        );
+        layer(&mut self.keyboard, file.keyboard, from_cli("keyboard"));
+        layer(&mut self.key_hold, file.key_hold, from_cli("key_hold"));
+        layer(&mut self.keymap, file.keymap.map(Some), from_cli("keymap"));
+        layer(
+            &mut self.save_dir,
+            file.save_dir.map(Some),
+            from_cli("save_dir"),
+        );
+        layer(&mut self.rewind, file.rewind, from_cli("rewind"));
+        layer(&mut self.rom_db, file.rom_db.map(Some), from_cli("rom_db"));
+        layer(&mut self.audio, file.audio, from_cli("audio"));
+        layer(
+            &mut self.audio_file,
+            file.audio_file.map(Some),
+            from_cli("audio_file"),
+        );
+    }
+
+    /// The merged settings, in config file format
+    pub fn effective(&self) -> ConfigFile {
+        ConfigFile {
+            frequency: match self.ipf {
+                Some(_) => None,
+                None => Some(self.instructions_per_second()),
+            },
+            ipf: self.ipf,
+            quirks: Some(self.quirks),
+            render: Some(self.render),
+            theme: Some(self.theme),
+            theme_file: self.theme_file.clone(),
+            persistence: Some(self.persistence),
+            keyboard: Some(self.keyboard),
+            key_hold: Some(self.key_hold),
+            keymap: self.keymap.clone(),
+            save_dir: self.save_dir.clone(),
+            rewind: Some(self.rewind),
+            rom_db: self.rom_db.clone(),
+            audio: Some(self.audio),
+            audio_file: self.audio_file.clone(),
+        }
+    }
+
+    pub fn trace_filter(&self) -> TraceFilter {
+        TraceFilter {
+            range: self.trace_range,
+            classes: self.trace_ops.clone(),
+        }
+    }
+
+    pub fn instructions_per_second(&self) -> f64 {
+        match (self.ipf, self.frequency) {
+            (Some(ipf), _) => (ipf as f64) * FRAME_RATE,
+            (None, Some(f)) => f,
+            (None, None) => 100.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layered(args: &[&str], file: &str) -> Config {
+        let matches = Config::command().try_get_matches_from(args).unwrap();
+        let mut cfg = Config::from_arg_matches(&matches).unwrap();
+        cfg.layer(
+            ConfigFile::parse(file, Path::new("/etc")).unwrap(),
+            &matches,
+        );
+        cfg
+    }
+
+    #[test]
+    fn test_config_file() {
+        let file =
+            "ipf = 15\nquirks = \"schip\"\nrender = \"half-block\"\nkeymap = \"keys.toml\"\n";
+        let cfg = layered(&["chip-8"], file);
+        assert_eq!(cfg.instructions_per_second(), 900.);
+        assert_eq!(cfg.quirks, QuirksPreset::Schip);
+        assert_eq!(cfg.render, RenderMode::HalfBlock);
+        assert_eq!(cfg.keymap.as_deref(), Some("/etc/keys.toml"));
+        assert_eq!(cfg.rewind, 10.);
+
+        // Flags win, even when they give the default value
+        let cfg = layered(&["chip-8", "--frequency", "500", "--render", "block"], file);
+        assert_eq!(cfg.instructions_per_second(), 500.);
+        assert_eq!(cfg.render, RenderMode::Block);
+        assert_eq!(cfg.quirks, QuirksPreset::Schip);
+
+        let matches = Config::command()
+            .try_get_matches_from(["chip-8", "--quirks", "vip"])
+            .unwrap();
+        let mut cfg = layered(&["chip-8", "--quirks", "vip"], file);
+        cfg.layer_rom(
+            RomInfo {
+                title: "Game".to_string(),
+                authors: Vec::new(),
+                platform: Some("xochip".to_string()),
+                ipf: Some(1000),
+                quirks: Some(QuirksPreset::Xochip),
+                keys: Vec::new(),
+            },
+            &matches,
+        );
+        assert_eq!(cfg.instructions_per_second(), 60000.);
+        assert_eq!(cfg.quirks, QuirksPreset::Vip);
+
+        assert!(ConfigFile::parse("speed = 1", Path::new("")).is_err());
+        assert!(ConfigFile::parse("ipf = 1\nfrequency = 60.0", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_effective_config() {
+        let cfg = layered(&["chip-8", "--theme", "amber"], "save-dir = \"/saves\"");
+        let text = toml::to_string(&cfg.effective()).unwrap();
+        assert!(text.contains("frequency = 100.0\n"));
+        assert!(text.contains("theme = \"amber\"\n"));
+        assert!(text.contains("save-dir = \"/saves\"\n"));
+        assert_eq!(
+            ConfigFile::parse(&text, Path::new("")).unwrap(),
+            cfg.effective()
+        );
+    }
 }
@@ -0,0 +1,728 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::emulator::{Emulator, EmulatorState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Run,
+    /// Execute one instruction then pause
+    Step,
+    /// Run until PC reaches the address, optionally only at or above a
+    /// stack depth so recursion does not stop early
+    Until {
+        pc: u16,
+        depth: Option<usize>,
+    },
+    /// Run until the stack drops below the depth
+    Out {
+        depth: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchTarget {
+    /// Inclusive RAM address range
+    Ram(u16, u16),
+    Reg(usize),
+    IReg,
+}
+
+/// Pause after an instruction that touches the target. Registers can only
+/// be watched for writes, which are detected as changes in value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub target: WatchTarget,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// Parse a hex address with an optional 0x prefix
+pub(crate) fn parse_addr(s: &str) -> Result<u16, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{}'", s))
+}
+
+fn parse_reg(s: &str) -> Option<usize> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(c), None) => c.to_digit(16).map(|x| x as usize),
+        _ => None,
+    }
+}
+
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    /// `[r|w|rw:]<addr>[-<addr>]` for RAM, `V0`-`VF` or `I` for registers
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(x) = parse_reg(s) {
+            return Ok(Watchpoint {
+                target: WatchTarget::Reg(x),
+                read: false,
+                write: true,
+            });
+        }
+        if s == "I" || s == "i" {
+            return Ok(Watchpoint {
+                target: WatchTarget::IReg,
+                read: false,
+                write: true,
+            });
+        }
+
+        let (read, write, range) = match s.split_once(':') {
+            Some(("r", range)) => (true, false, range),
+            Some(("w", range)) => (false, true, range),
+            Some(("rw", range)) => (true, true, range),
+            Some((kind, _)) => return Err(format!("unknown access kind '{}'", kind)),
+            None => (true, true, s),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_addr(start)?, parse_addr(end)?),
+            None => (parse_addr(range)?, parse_addr(range)?),
+        };
+        if end < start {
+            return Err(format!("empty address range '{}'", range));
+        }
+
+        Ok(Watchpoint {
+            target: WatchTarget::Ram(start, end),
+            read,
+            write,
+        })
+    }
+}
+
+/// PC breakpoint given on the command line, `<addr>` or `<addr> if <cond>`
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub condition: Option<Condition>,
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(" if ") {
+            Some((addr, cond)) => Ok(Breakpoint {
+                addr: parse_addr(addr.trim())?,
+                condition: Some(cond.parse()?),
+            }),
+            None => Ok(Breakpoint {
+                addr: parse_addr(s.trim())?,
+                condition: None,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Reg(usize),
+    IReg,
+    Pc,
+    Delay,
+    Sound,
+    Literal(u16),
+    /// Byte of RAM at an address
+    Mem(Box<Operand>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Cmp(Operand, CmpOp, Operand),
+}
+
+/// Boolean expression over the machine state, e.g. `V3 == 0x10 && I > 0x300`.
+/// Operands are `V0`-`VF`, `I`, `PC`, `DT`, `ST`, numbers and `[addr]` for
+/// a byte of RAM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn eval(&self, state: &EmulatorState) -> bool {
+        eval_expr(&self.expr, state)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+fn eval_expr(expr: &Expr, state: &EmulatorState) -> bool {
+    match expr {
+        Expr::Or(a, b) => eval_expr(a, state) || eval_expr(b, state),
+        Expr::And(a, b) => eval_expr(a, state) && eval_expr(b, state),
+        Expr::Cmp(a, op, b) => {
+            let (a, b) = (eval_operand(a, state), eval_operand(b, state));
+            match op {
+                CmpOp::Eq => a == b,
+                CmpOp::Ne => a != b,
+                CmpOp::Lt => a < b,
+                CmpOp::Le => a <= b,
+                CmpOp::Gt => a > b,
+                CmpOp::Ge => a >= b,
+            }
+        }
+    }
+}
+
+fn eval_operand(operand: &Operand, state: &EmulatorState) -> u16 {
+    match operand {
+        Operand::Reg(x) => state.register_bank[*x] as u16,
+        Operand::IReg => state.ireg,
+        Operand::Pc => state.pc,
+        Operand::Delay => state.delay_timer as u16,
+        Operand::Sound => state.sound_timer as u16,
+        Operand::Literal(n) => *n,
+        Operand::Mem(addr) => *state
+            .ram
+            .get(eval_operand(addr, state) as usize)
+            .unwrap_or(&0) as u16,
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = CondParser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(tok) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected '{}' in condition", tok));
+        }
+
+        Ok(Condition {
+            text: s.trim().to_string(),
+            expr,
+        })
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+        if c.is_whitespace() {
+            i += 1;
+        } else if ["==", "!=", "<=", ">=", "&&", "||"].contains(&pair.as_str()) {
+            tokens.push(pair);
+            i += 2;
+        } else if "<>()[]".contains(c) {
+            tokens.push(c.to_string());
+            i += 1;
+        } else if c.is_ascii_alphanumeric() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else {
+            return Err(format!("unexpected '{}' in condition", c));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct CondParser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl CondParser {
+    fn next(&mut self) -> Result<String, String> {
+        let tok = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| String::from("unexpected end of condition"))?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    fn eat(&mut self, tok: &str) -> bool {
+        let found = self.tokens.get(self.pos).is_some_and(|t| t == tok);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.cmp()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.cmp()?));
+        }
+        Ok(expr)
+    }
+
+    fn cmp(&mut self) -> Result<Expr, String> {
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err(String::from("missing ')' in condition"));
+            }
+            return Ok(expr);
+        }
+
+        let a = self.operand()?;
+        let op = match self.next()?.as_str() {
+            "==" => CmpOp::Eq,
+            "!=" => CmpOp::Ne,
+            "<" => CmpOp::Lt,
+            "<=" => CmpOp::Le,
+            ">" => CmpOp::Gt,
+            ">=" => CmpOp::Ge,
+            tok => return Err(format!("expected a comparison, found '{}'", tok)),
+        };
+        let b = self.operand()?;
+        Ok(Expr::Cmp(a, op, b))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        let tok = self.next()?;
+        if tok == "[" {
+            let addr = self.operand()?;
+            if !self.eat("]") {
+                return Err(String::from("missing ']' in condition"));
+            }
+            return Ok(Operand::Mem(Box::new(addr)));
+        }
+        if let Some(x) = parse_reg(&tok) {
+            return Ok(Operand::Reg(x));
+        }
+
+        let upper = tok.to_uppercase();
+        match upper.as_str() {
+            "I" => Ok(Operand::IReg),
+            "PC" => Ok(Operand::Pc),
+            "DT" => Ok(Operand::Delay),
+            "ST" => Ok(Operand::Sound),
+            _ => {
+                let n = match upper.strip_prefix("0X") {
+                    Some(hex) => u16::from_str_radix(hex, 16),
+                    None => tok.parse(),
+                };
+                n.map(Operand::Literal)
+                    .map_err(|_| format!("invalid operand '{}'", tok))
+            }
+        }
+    }
+}
+
+/// Run control for the main loop: pause, stepping, breakpoints and
+/// watchpoints
+#[derive(Debug)]
+pub struct Debugger {
+    paused: bool,
+    mode: Mode,
+    breakpoints: BTreeSet<u16>,
+    /// Breakpoints that only stop when their condition holds
+    conditions: BTreeMap<u16, Condition>,
+    /// Stop when any of these becomes true, paired with the last result
+    break_when: Vec<(Condition, bool)>,
+    watchpoints: Vec<Watchpoint>,
+    /// PC and registers before the current instruction, for watchpoints
+    before: Option<(u16, Vec<u8>, u16)>,
+    /// Let the first instruction after resuming run even if it has a
+    /// breakpoint, otherwise continuing from one would stop immediately
+    skip_break: bool,
+    stop: Option<String>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            paused: false,
+            mode: Mode::Run,
+            breakpoints: BTreeSet::new(),
+            conditions: BTreeMap::new(),
+            break_when: Vec::new(),
+            watchpoints: Vec::new(),
+            before: None,
+            skip_break: false,
+            stop: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    /// Returns whether a breakpoint is now set at `addr`
+    pub fn toggle_breakpoint(&mut self, addr: u16) -> bool {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+            return true;
+        }
+        self.conditions.remove(&addr);
+        false
+    }
+
+    pub fn add_breakpoint(&mut self, bp: Breakpoint) {
+        self.breakpoints.insert(bp.addr);
+        match bp.condition {
+            Some(cond) => self.conditions.insert(bp.addr, cond),
+            None => self.conditions.remove(&bp.addr),
+        };
+    }
+
+    /// Stop before the instruction where `cond` changes from false to true
+    pub fn add_break_when(&mut self, cond: Condition) {
+        self.break_when.push((cond, false));
+    }
+
+    pub fn add_watchpoint(&mut self, watch: Watchpoint) {
+        self.watchpoints.push(watch);
+    }
+
+    /// Whether the emulator needs to log RAM accesses for the watchpoints
+    pub fn watches_ram(&self) -> bool {
+        self.watchpoints
+            .iter()
+            .any(|w| matches!(w.target, WatchTarget::Ram(_, _)))
+    }
+
+    pub fn pause(&mut self, state: &EmulatorState) {
+        self.stop_at(state, "Paused");
+    }
+
+    pub fn resume(&mut self) {
+        self.run(Mode::Run);
+    }
+
+    pub fn toggle_pause(&mut self, state: &EmulatorState) {
+        match self.paused {
+            true => self.resume(),
+            false => self.pause(state),
+        }
+    }
+
+    /// Execute a single instruction, pausing first if running
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.mode = Mode::Step;
+    }
+
+    /// Like `step` but runs a whole subroutine when PC is at a 2NNN call
+    pub fn step_over(&mut self, state: &EmulatorState) {
+        let pc = state.pc as usize;
+        let is_call = state.ram.get(pc).is_some_and(|b| b >> 4 == 0x2);
+        match is_call {
+            true => self.run(Mode::Until {
+                pc: state.pc.wrapping_add(2),
+                depth: Some(state.stack_len),
+            }),
+            false => self.step(),
+        }
+    }
+
+    /// Run until the current subroutine returns. Returns false when not in one.
+    pub fn step_out(&mut self, state: &EmulatorState) -> bool {
+        if state.stack_len == 0 {
+            return false;
+        }
+        self.run(Mode::Out {
+            depth: state.stack_len,
+        });
+        true
+    }
+
+    pub fn run_to(&mut self, addr: u16) {
+        self.run(Mode::Until {
+            pc: addr,
+            depth: None,
+        });
+    }
+
+    /// Called before each instruction, returns whether it should execute
+    pub fn before_step(&mut self, ch8: &Emulator) -> bool {
+        let state = ch8.get_state();
+        self.before = (!self.watchpoints.is_empty())
+            .then(|| (state.pc, state.register_bank.clone(), state.ireg));
+
+        // Conditions are tracked even while paused so stepping onto an
+        // already true condition does not count as a change
+        let mut met = None;
+        for (cond, last) in self.break_when.iter_mut() {
+            let now = cond.eval(state);
+            if now && !*last && met.is_none() {
+                met = Some(cond.to_string());
+            }
+            *last = now;
+        }
+
+        if self.paused {
+            return self.mode == Mode::Step;
+        }
+
+        let skip_break = std::mem::take(&mut self.skip_break);
+        if !skip_break
+            && self.breakpoints.contains(&state.pc)
+            && self.conditions.get(&state.pc).is_none_or(|c| c.eval(state))
+        {
+            self.stop_at(state, "Breakpoint");
+            return false;
+        }
+        if let Some(cond) = met {
+            self.stop_at(state, &format!("{} became true", cond));
+            return false;
+        }
+        if let Mode::Until { pc, depth } = self.mode {
+            if state.pc == pc && depth.is_none_or(|d| state.stack_len <= d) {
+                self.stop_at(state, "Stopped");
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Called after each instruction executes
+    pub fn after_step(&mut self, ch8: &Emulator) {
+        let state = ch8.get_state();
+        if let Some(hit) = self.watch_hit(ch8) {
+            self.paused = true;
+            self.mode = Mode::Run;
+            self.stop = Some(hit);
+            return;
+        }
+
+        match self.mode {
+            Mode::Step => self.stop_at(state, "Stepped"),
+            Mode::Out { depth } if state.stack_len < depth => self.stop_at(state, "Returned"),
+            _ => {}
+        }
+    }
+
+    /// Description of the first watchpoint the last instruction triggered
+    fn watch_hit(&self, ch8: &Emulator) -> Option<String> {
+        let (pc, regs, ireg) = self.before.as_ref()?;
+        let state = ch8.get_state();
+
+        for watch in self.watchpoints.iter() {
+            match watch.target {
+                WatchTarget::Ram(start, end) => {
+                    let hit = ch8.accesses().iter().find(|a| {
+                        (start as usize..=end as usize).contains(&a.addr)
+                            && ((a.write && watch.write) || (!a.write && watch.read))
+                    });
+                    if let Some(access) = hit {
+                        let kind = if access.write {
+                            "Write to"
+                        } else {
+                            "Read from"
+                        };
+                        return Some(format!("{} {:04X} at {:04X}", kind, access.addr, pc));
+                    }
+                }
+                WatchTarget::Reg(x) if regs[x] != state.register_bank[x] => {
+                    return Some(format!(
+                        "V{:X} changed {:02X} -> {:02X} at {:04X}",
+                        x, regs[x], state.register_bank[x], pc
+                    ));
+                }
+                WatchTarget::IReg if *ireg != state.ireg => {
+                    return Some(format!(
+                        "I changed {:04X} -> {:04X} at {:04X}",
+                        ireg, state.ireg, pc
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Description of why execution last paused, if not yet reported
+    pub fn take_stop(&mut self) -> Option<String> {
+        self.stop.take()
+    }
+
+    fn run(&mut self, mode: Mode) {
+        self.paused = false;
+        self.mode = mode;
+        self.skip_break = true;
+    }
+
+    fn stop_at(&mut self, state: &EmulatorState, reason: &str) {
+        self.paused = true;
+        self.mode = Mode::Run;
+        self.stop = Some(format!("{} at {:04X}", reason, state.pc));
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::emulator::Emulator;
+
+    /// Run up to `n` instructions under the debugger
+    fn run(ch8: &mut Emulator, debugger: &mut Debugger, n: usize) {
+        for _ in 0..n {
+            if !debugger.before_step(ch8) {
+                break;
+            }
+            ch8.step(0).unwrap();
+            debugger.after_step(ch8);
+        }
+    }
+
+    fn load(source: &str) -> Emulator {
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&assemble(source).unwrap()).unwrap();
+        ch8
+    }
+
+    const PROG: &str = "
+        : main
+            v0 := 1
+            inc
+            v2 := 3
+            jump main
+        : inc
+            v1 += 1
+            v1 += 1
+            return
+    ";
+
+    #[test]
+    fn test_breakpoints() {
+        let mut ch8 = load(PROG);
+        let mut debugger = Debugger::new();
+        assert!(debugger.toggle_breakpoint(0x208));
+
+        run(&mut ch8, &mut debugger, 100);
+        assert!(debugger.is_paused());
+        assert_eq!(ch8.get_state().pc, 0x208);
+        assert_eq!(debugger.take_stop().unwrap(), "Breakpoint at 0208");
+
+        // Continuing runs past the breakpoint until it is hit again
+        debugger.resume();
+        run(&mut ch8, &mut debugger, 100);
+        assert_eq!(ch8.get_state().pc, 0x208);
+        assert_eq!(ch8.get_state().register_bank[1], 2);
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut ch8 = load(PROG);
+        let mut debugger = Debugger::new();
+        debugger.pause(ch8.get_state());
+
+        debugger.step();
+        run(&mut ch8, &mut debugger, 10);
+        assert_eq!(ch8.get_state().pc, 0x202);
+
+        debugger.step_over(ch8.get_state());
+        run(&mut ch8, &mut debugger, 10);
+        assert_eq!(ch8.get_state().pc, 0x204);
+        assert_eq!(ch8.get_state().register_bank[1], 2);
+
+        debugger.run_to(0x208);
+        run(&mut ch8, &mut debugger, 10);
+        assert_eq!(ch8.get_state().pc, 0x208);
+
+        assert!(debugger.step_out(ch8.get_state()));
+        run(&mut ch8, &mut debugger, 10);
+        assert_eq!(ch8.get_state().pc, 0x204);
+        assert!(!debugger.step_out(ch8.get_state()));
+    }
+
+    #[test]
+    fn test_conditions() {
+        let cond: Condition = "V1 == 2 && (I > 0x300 || [0x200] != 0x60)".parse().unwrap();
+        let mut ch8 = load(PROG);
+        assert!(!cond.eval(ch8.get_state()));
+
+        let mut state = ch8.get_state().clone();
+        state.register_bank[1] = 2;
+        state.ireg = 0x301;
+        ch8.load_state(&state).unwrap();
+        assert!(cond.eval(ch8.get_state()));
+
+        assert!("V1 = 2".parse::<Condition>().is_err());
+        assert!("VG == 2".parse::<Condition>().is_err());
+
+        // A conditional breakpoint in the subroutine stops on the second call
+        let mut ch8 = load(PROG);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint("20A if V1 == 3".parse().unwrap());
+        run(&mut ch8, &mut debugger, 100);
+        assert_eq!(ch8.get_state().pc, 0x20A);
+        assert_eq!(ch8.get_state().register_bank[1], 3);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut ch8 = load(
+            "
+            : main
+                v0 := 7
+                i := 0x300
+                bcd v0
+                v5 := 1
+            ",
+        );
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint("w:302".parse().unwrap());
+        debugger.add_watchpoint("V5".parse().unwrap());
+        ch8.set_access_log(debugger.watches_ram());
+
+        run(&mut ch8, &mut debugger, 10);
+        assert_eq!(debugger.take_stop().unwrap(), "Write to 0302 at 0204");
+
+        debugger.resume();
+        run(&mut ch8, &mut debugger, 10);
+        assert_eq!(debugger.take_stop().unwrap(), "V5 changed 00 -> 01 at 0206");
+
+        assert!("x:300".parse::<Watchpoint>().is_err());
+        assert!("300-2FF".parse::<Watchpoint>().is_err());
+    }
+}
@@ -0,0 +1,571 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+
+use clap::ValueEnum;
+
+use crate::file_io::read_program;
+use crate::ops::{decode, Ops, Src};
+use crate::quirks::Quirks;
+
+/// Address programs are loaded at
+pub const PROG_START: u16 = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Syntax {
+    /// Mnemonics from Cowgod's technical reference, e.g. `LD V1, 0x05`
+    Cowgod,
+    /// Octo assembly, e.g. `v1 := 0x05`
+    Octo,
+}
+
+/// One decoded instruction. Words that do not decode are kept with `op` unset
+/// and print as raw data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    op: Option<Ops>,
+    /// BNNN jumps to XNN + VX, as with `Quirks::jump_vx`
+    jump_vx: bool,
+    /// The jump or call target has a label, which is printed instead
+    labelled: bool,
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> u16 {
+        ((self.bytes[0] as u16) << 8) + (*self.bytes.get(1).unwrap_or(&0) as u16)
+    }
+
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.op.is_some()
+    }
+
+    pub(crate) fn op(&self) -> Option<Ops> {
+        self.op
+    }
+
+    /// Print BNNN as the quirks execute it
+    pub fn with_quirks(mut self, quirks: &Quirks) -> Self {
+        self.jump_vx = quirks.jump_vx;
+        self
+    }
+
+    /// Address a jump or call goes to, the base address for BNNN
+    pub fn target(&self) -> Option<u16> {
+        match self.op? {
+            Ops::Jump(Src::Literal(n))
+            | Ops::CallSubRoutine(Src::Literal(n))
+            | Ops::JumpRelative(Src::Literal(n)) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn target_name(&self, src: &Src) -> String {
+        match self.labelled {
+            true => format!("L{:04X}", lit(src)),
+            false => format!("0x{:03X}", lit(src)),
+        }
+    }
+
+    pub fn format(&self, syntax: Syntax) -> String {
+        match syntax {
+            Syntax::Cowgod => self.to_string(),
+            Syntax::Octo => Octo(self).to_string(),
+        }
+    }
+}
+
+/// Decode the instruction at `offset` in `bytes`, which are loaded at `base_addr`
+pub fn decode_at(bytes: &[u8], base_addr: u16, offset: usize) -> Instruction {
+    let addr = base_addr.wrapping_add(offset as u16);
+    if offset + 1 >= bytes.len() {
+        return Instruction {
+            addr,
+            bytes: bytes[offset..].to_vec(),
+            op: None,
+            jump_vx: false,
+            labelled: false,
+        };
+    }
+
+    let opcode = ((bytes[offset] as u16) << 8) + (bytes[offset + 1] as u16);
+    if opcode == 0xF000 {
+        if offset + 3 >= bytes.len() {
+            return Instruction {
+                addr,
+                bytes: bytes[offset..(offset + 2)].to_vec(),
+                op: None,
+                jump_vx: false,
+                labelled: false,
+            };
+        }
+        let next = ((bytes[offset + 2] as u16) << 8) + (bytes[offset + 3] as u16);
+        return Instruction {
+            addr,
+            bytes: bytes[offset..(offset + 4)].to_vec(),
+            op: decode(opcode, next),
+            jump_vx: false,
+            labelled: false,
+        };
+    }
+
+    Instruction {
+        addr,
+        bytes: bytes[offset..(offset + 2)].to_vec(),
+        op: decode(opcode, 0),
+        jump_vx: false,
+        labelled: false,
+    }
+}
+
+/// Linear sweep: decode every word in order, without telling code from data
+pub fn disassemble(bytes: &[u8], base_addr: u16) -> Vec<Instruction> {
+    let mut out = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instr = decode_at(bytes, base_addr, offset);
+        offset += instr.bytes.len();
+        out.push(instr);
+    }
+
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Code(Instruction),
+    Data { addr: u16, bytes: Vec<u8> },
+}
+
+/// Result of following control flow through a program
+pub struct Listing {
+    pub items: Vec<Item>,
+    /// Addresses that are the target of a jump or call
+    pub labels: BTreeSet<u16>,
+}
+
+/// Follow jumps, calls and skips from `base_addr` so that only reachable
+/// instructions are decoded, everything else is reported as data
+pub fn trace(bytes: &[u8], base_addr: u16) -> Listing {
+    let mut is_code = vec![false; bytes.len()];
+    let mut starts: BTreeSet<usize> = BTreeSet::new();
+    let mut labels: BTreeSet<u16> = BTreeSet::new();
+    let mut todo: Vec<usize> = vec![0];
+
+    let offset_of = |addr: u16| -> Option<usize> {
+        let off = addr.wrapping_sub(base_addr) as usize;
+        (addr >= base_addr && off < bytes.len()).then_some(off)
+    };
+
+    while let Some(mut offset) = todo.pop() {
+        while offset < bytes.len() && !starts.contains(&offset) {
+            let instr = decode_at(bytes, base_addr, offset);
+            let op = match instr.op {
+                Some(op) if offset + instr.bytes.len() <= bytes.len() => op,
+                _ => break,
+            };
+            if is_code[offset..(offset + instr.bytes.len())]
+                .iter()
+                .any(|c| *c)
+            {
+                // Overlaps an instruction decoded from a different alignment
+                break;
+            }
+
+            starts.insert(offset);
+            for c in is_code[offset..(offset + instr.bytes.len())].iter_mut() {
+                *c = true;
+            }
+            let next = offset + instr.bytes.len();
+
+            match op {
+                Ops::Jump(Src::Literal(n)) => {
+                    labels.insert(n);
+                    if let Some(target) = offset_of(n) {
+                        todo.push(target);
+                    }
+                    break;
+                }
+                Ops::CallSubRoutine(Src::Literal(n)) => {
+                    labels.insert(n);
+                    if let Some(target) = offset_of(n) {
+                        todo.push(target);
+                    }
+                }
+                Ops::JumpEq(_, _) | Ops::JumpNeq(_, _) => {
+                    // The skipped instruction may be four bytes long
+                    let skipped = decode_at(bytes, base_addr, next.min(bytes.len() - 1));
+                    todo.push(next + skipped.bytes.len());
+                }
+                // Computed jumps can go anywhere, the rest of the program is
+                // found only through other references
+                Ops::ReturnSubRoutine | Ops::Exit | Ops::JumpRelative(_) => break,
+                _ => {}
+            }
+
+            offset = next;
+        }
+    }
+
+    let mut items = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        if starts.contains(&offset) {
+            let instr = decode_at(bytes, base_addr, offset);
+            offset += instr.bytes.len();
+            items.push(Item::Code(instr));
+        } else {
+            let start = offset;
+            while offset < bytes.len() && !starts.contains(&offset) {
+                offset += 1;
+            }
+            items.push(Item::Data {
+                addr: base_addr.wrapping_add(start as u16),
+                bytes: bytes[start..offset].to_vec(),
+            });
+        }
+    }
+
+    Listing { items, labels }
+}
+
+/// Print a listing of a ROM file to stdout
+pub fn run(rom: &str, syntax: Syntax, linear: bool, quirks: &Quirks) -> Result<(), Box<dyn Error>> {
+    let bytes = read_program(rom)?;
+    let mut listing = match linear {
+        true => Listing {
+            items: disassemble(&bytes, PROG_START)
+                .into_iter()
+                .map(Item::Code)
+                .collect(),
+            labels: BTreeSet::new(),
+        },
+        false => trace(&bytes, PROG_START),
+    };
+    for item in listing.items.iter_mut() {
+        if let Item::Code(instr) = item {
+            *instr = instr.clone().with_quirks(quirks);
+        }
+    }
+
+    print!("{}", format_listing(&listing, syntax));
+    Ok(())
+}
+
+pub fn format_listing(listing: &Listing, syntax: Syntax) -> String {
+    let mut out = String::new();
+
+    // Targets can only be named when their label is printed
+    let mut printed = BTreeSet::new();
+    for item in listing.items.iter() {
+        match item {
+            Item::Code(instr) => {
+                printed.insert(instr.addr);
+            }
+            Item::Data { addr, bytes } => {
+                printed.extend((0..bytes.len()).step_by(8).map(|i| addr + i as u16));
+            }
+        }
+    }
+    printed.retain(|addr| listing.labels.contains(addr));
+
+    for item in listing.items.iter() {
+        match item {
+            Item::Code(instr) => {
+                if printed.contains(&instr.addr) {
+                    out.push_str(&label(instr.addr, syntax));
+                }
+                let instr = &Instruction {
+                    labelled: instr.target().is_some_and(|n| printed.contains(&n)),
+                    ..instr.clone()
+                };
+                let raw: String = instr.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                out.push_str(&match syntax {
+                    Syntax::Cowgod => format!("{:04X}  {:<8}  {}\n", instr.addr, raw, instr),
+                    Syntax::Octo => {
+                        format!("\t{:<24} # {:04X}  {}\n", Octo(instr), instr.addr, raw)
+                    }
+                });
+            }
+            Item::Data { addr, bytes } => {
+                for (i, chunk) in bytes.chunks(8).enumerate() {
+                    let addr = addr + (i * 8) as u16;
+                    if printed.contains(&addr) {
+                        out.push_str(&label(addr, syntax));
+                    }
+                    out.push_str(&match syntax {
+                        Syntax::Cowgod => format!(
+                            "{:04X}  {:<8}  DB {}\n",
+                            addr,
+                            "",
+                            chunk
+                                .iter()
+                                .map(|b| format!("0x{:02X}", b))
+                                .collect::<Vec<String>>()
+                                .join(", ")
+                        ),
+                        Syntax::Octo => format!(
+                            "\t{:<24} # {:04X}\n",
+                            chunk
+                                .iter()
+                                .map(|b| format!("0x{:02X}", b))
+                                .collect::<Vec<String>>()
+                                .join(" "),
+                            addr
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn label(addr: u16, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Cowgod => format!("L{:04X}:\n", addr),
+        Syntax::Octo => format!(": L{:04X}\n", addr),
+    }
+}
+
+fn reg(src: &Src) -> String {
+    match src {
+        Src::Reg(x) => format!("{:X}", x),
+        _ => String::from("?"),
+    }
+}
+
+fn lit(src: &Src) -> u16 {
+    match src {
+        Src::Literal(n) => *n,
+        _ => 0,
+    }
+}
+
+/// Cowgod syntax
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match &self.op {
+            Some(op) => op,
+            None => {
+                let bytes: Vec<String> =
+                    self.bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                return write!(f, "DB {}", bytes.join(", "));
+            }
+        };
+
+        match op {
+            Ops::DisplayClear => write!(f, "CLS"),
+            Ops::ReturnSubRoutine => write!(f, "RET"),
+            Ops::ScrollDown(n) => write!(f, "SCD {}", lit(n)),
+            Ops::ScrollUp(n) => write!(f, "SCU {}", lit(n)),
+            Ops::ScrollRight => write!(f, "SCR"),
+            Ops::ScrollLeft => write!(f, "SCL"),
+            Ops::Exit => write!(f, "EXIT"),
+            Ops::LowRes => write!(f, "LOW"),
+            Ops::HighRes => write!(f, "HIGH"),
+            Ops::Jump(n) => write!(f, "JP {}", self.target_name(n)),
+            Ops::CallSubRoutine(n) => write!(f, "CALL {}", self.target_name(n)),
+            Ops::JumpRelative(n) if self.jump_vx => {
+                write!(f, "JP V{:X}, {}", lit(n) >> 8, self.target_name(n))
+            }
+            Ops::JumpRelative(n) => write!(f, "JP V0, {}", self.target_name(n)),
+            Ops::JumpEq(Src::Key, x) => write!(f, "SKP V{}", reg(x)),
+            Ops::JumpNeq(Src::Key, x) => write!(f, "SKNP V{}", reg(x)),
+            Ops::JumpEq(x, Src::Literal(n)) => write!(f, "SE V{}, 0x{:02X}", reg(x), n),
+            Ops::JumpNeq(x, Src::Literal(n)) => write!(f, "SNE V{}, 0x{:02X}", reg(x), n),
+            Ops::JumpEq(x, y) => write!(f, "SE V{}, V{}", reg(x), reg(y)),
+            Ops::JumpNeq(x, y) => write!(f, "SNE V{}, V{}", reg(x), reg(y)),
+            Ops::Add(Src::IReg, Src::IReg, x) => write!(f, "ADD I, V{}", reg(x)),
+            Ops::Add(Src::IReg, n, _) => write!(f, "LD I, 0x{:03X}", lit(n)),
+            Ops::Add(x, Src::Literal(n), _) => write!(f, "LD V{}, 0x{:02X}", reg(x), n),
+            Ops::Add(x, y, Src::Literal(0)) => write!(f, "LD V{}, V{}", reg(x), reg(y)),
+            Ops::Add(x, _, Src::Literal(n)) => write!(f, "ADD V{}, 0x{:02X}", reg(x), n),
+            Ops::Add(x, _, y) => write!(f, "ADD V{}, V{}", reg(x), reg(y)),
+            Ops::Sub(d, x, y) if d == x => write!(f, "SUB V{}, V{}", reg(d), reg(y)),
+            Ops::Sub(d, x, _) => write!(f, "SUBN V{}, V{}", reg(d), reg(x)),
+            Ops::Or(x, _, y) => write!(f, "OR V{}, V{}", reg(x), reg(y)),
+            Ops::And(x, _, y) => write!(f, "AND V{}, V{}", reg(x), reg(y)),
+            Ops::Xor(x, _, y) => write!(f, "XOR V{}, V{}", reg(x), reg(y)),
+            Ops::RShift(x, y) => write!(f, "SHR V{}, V{}", reg(x), reg(y)),
+            Ops::LShift(x, y) => write!(f, "SHL V{}, V{}", reg(x), reg(y)),
+            Ops::Rand(x, n) => write!(f, "RND V{}, 0x{:02X}", reg(x), lit(n)),
+            Ops::DisplayUpdate(x, y, n) => write!(f, "DRW V{}, V{}, {}", reg(x), reg(y), lit(n)),
+            Ops::ReadDelay(x) => write!(f, "LD V{}, DT", reg(x)),
+            Ops::GetKey(x) => write!(f, "LD V{}, K", reg(x)),
+            Ops::WriteDelay(x) => write!(f, "LD DT, V{}", reg(x)),
+            Ops::WriteSound(x) => write!(f, "LD ST, V{}", reg(x)),
+            Ops::GetSprite(x) => write!(f, "LD F, V{}", reg(x)),
+            Ops::GetBigSprite(x) => write!(f, "LD HF, V{}", reg(x)),
+            Ops::BCD(x) => write!(f, "LD B, V{}", reg(x)),
+            Ops::RegDump(x) => write!(f, "LD [I], V{}", reg(x)),
+            Ops::RegLoad(x) => write!(f, "LD V{}, [I]", reg(x)),
+            Ops::FlagsDump(x) => write!(f, "LD R, V{}", reg(x)),
+            Ops::FlagsLoad(x) => write!(f, "LD V{}, R", reg(x)),
+            Ops::RangeDump(x, y) => write!(f, "SAVE V{}, V{}", reg(x), reg(y)),
+            Ops::RangeLoad(x, y) => write!(f, "LOAD V{}, V{}", reg(x), reg(y)),
+            Ops::LongLoad(n) => write!(f, "LD I, LONG 0x{:04X}", lit(n)),
+            Ops::SelectPlanes(n) => write!(f, "PLANE {}", lit(n)),
+            Ops::LoadAudio => write!(f, "AUDIO"),
+            Ops::SetPitch(x) => write!(f, "PITCH V{}", reg(x)),
+        }
+    }
+}
+
+/// Octo syntax for an instruction
+pub struct Octo<'a>(pub &'a Instruction);
+
+impl fmt::Display for Octo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match &self.0.op {
+            Some(op) => op,
+            None => {
+                let bytes: Vec<String> = self
+                    .0
+                    .bytes
+                    .iter()
+                    .map(|b| format!("0x{:02X}", b))
+                    .collect();
+                return f.pad(&bytes.join(" "));
+            }
+        };
+
+        // Octo's `if` describes when the next instruction runs, which is the
+        // inverse of the skip condition
+        let text = match op {
+            Ops::DisplayClear => String::from("clear"),
+            Ops::ReturnSubRoutine => String::from("return"),
+            Ops::ScrollDown(n) => format!("scroll-down {}", lit(n)),
+            Ops::ScrollUp(n) => format!("scroll-up {}", lit(n)),
+            Ops::ScrollRight => String::from("scroll-right"),
+            Ops::ScrollLeft => String::from("scroll-left"),
+            Ops::Exit => String::from("exit"),
+            Ops::LowRes => String::from("lores"),
+            Ops::HighRes => String::from("hires"),
+            Ops::Jump(n) => format!("jump {}", self.0.target_name(n)),
+            Ops::CallSubRoutine(n) => format!(":call {}", self.0.target_name(n)),
+            Ops::JumpRelative(n) => format!("jump0 {}", self.0.target_name(n)),
+            Ops::JumpEq(Src::Key, x) => format!("if v{} -key then", reg(x)),
+            Ops::JumpNeq(Src::Key, x) => format!("if v{} key then", reg(x)),
+            Ops::JumpEq(x, Src::Literal(n)) => format!("if v{} != 0x{:02X} then", reg(x), n),
+            Ops::JumpNeq(x, Src::Literal(n)) => format!("if v{} == 0x{:02X} then", reg(x), n),
+            Ops::JumpEq(x, y) => format!("if v{} != v{} then", reg(x), reg(y)),
+            Ops::JumpNeq(x, y) => format!("if v{} == v{} then", reg(x), reg(y)),
+            Ops::Add(Src::IReg, Src::IReg, x) => format!("i += v{}", reg(x)),
+            Ops::Add(Src::IReg, n, _) => format!("i := 0x{:03X}", lit(n)),
+            Ops::Add(x, Src::Literal(n), _) => format!("v{} := 0x{:02X}", reg(x), n),
+            Ops::Add(x, y, Src::Literal(0)) => format!("v{} := v{}", reg(x), reg(y)),
+            Ops::Add(x, _, Src::Literal(n)) => format!("v{} += 0x{:02X}", reg(x), n),
+            Ops::Add(x, _, y) => format!("v{} += v{}", reg(x), reg(y)),
+            Ops::Sub(d, x, y) if d == x => format!("v{} -= v{}", reg(d), reg(y)),
+            Ops::Sub(d, x, _) => format!("v{} =- v{}", reg(d), reg(x)),
+            Ops::Or(x, _, y) => format!("v{} |= v{}", reg(x), reg(y)),
+            Ops::And(x, _, y) => format!("v{} &= v{}", reg(x), reg(y)),
+            Ops::Xor(x, _, y) => format!("v{} ^= v{}", reg(x), reg(y)),
+            Ops::RShift(x, y) => format!("v{} >>= v{}", reg(x), reg(y)),
+            Ops::LShift(x, y) => format!("v{} <<= v{}", reg(x), reg(y)),
+            Ops::Rand(x, n) => format!("v{} := random 0x{:02X}", reg(x), lit(n)),
+            Ops::DisplayUpdate(x, y, n) => format!("sprite v{} v{} {}", reg(x), reg(y), lit(n)),
+            Ops::ReadDelay(x) => format!("v{} := delay", reg(x)),
+            Ops::GetKey(x) => format!("v{} := key", reg(x)),
+            Ops::WriteDelay(x) => format!("delay := v{}", reg(x)),
+            Ops::WriteSound(x) => format!("buzzer := v{}", reg(x)),
+            Ops::GetSprite(x) => format!("i := hex v{}", reg(x)),
+            Ops::GetBigSprite(x) => format!("i := bighex v{}", reg(x)),
+            Ops::BCD(x) => format!("bcd v{}", reg(x)),
+            Ops::RegDump(x) => format!("save v{}", reg(x)),
+            Ops::RegLoad(x) => format!("load v{}", reg(x)),
+            Ops::FlagsDump(x) => format!("saveflags v{}", reg(x)),
+            Ops::FlagsLoad(x) => format!("loadflags v{}", reg(x)),
+            Ops::RangeDump(x, y) => format!("save v{} - v{}", reg(x), reg(y)),
+            Ops::RangeLoad(x, y) => format!("load v{} - v{}", reg(x), reg(y)),
+            Ops::LongLoad(n) => format!("i := long 0x{:04X}", lit(n)),
+            Ops::SelectPlanes(n) => format!("plane {}", lit(n)),
+            Ops::LoadAudio => String::from("audio"),
+            Ops::SetPitch(x) => format!("pitch := v{}", reg(x)),
+        };
+
+        f.pad(&text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let prog = [
+            0x00, 0xE0, 0x6A, 0x05, 0x8A, 0xB7, 0xF0, 0x00, 0x12, 0x34, 0xFA, 0x1E,
+        ];
+        let instrs = disassemble(&prog, PROG_START);
+
+        let cowgod: Vec<String> = instrs.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            cowgod,
+            vec![
+                "CLS",
+                "LD VA, 0x05",
+                "SUBN VA, VB",
+                "LD I, LONG 0x1234",
+                "ADD I, VA"
+            ]
+        );
+
+        let octo: Vec<String> = instrs.iter().map(|i| Octo(i).to_string()).collect();
+        assert_eq!(
+            octo,
+            vec![
+                "clear",
+                "vA := 0x05",
+                "vA =- vB",
+                "i := long 0x1234",
+                "i += vA"
+            ]
+        );
+        assert_eq!(instrs[4].addr, 0x20A);
+    }
+
+    #[test]
+    fn test_trace_separates_data() {
+        // call 0x206, jump to self, data, then the subroutine
+        let prog = [0x22, 0x06, 0x12, 0x02, 0xFF, 0x81, 0xA2, 0x04, 0x00, 0xEE];
+        let listing = trace(&prog, PROG_START);
+
+        assert_eq!(listing.labels, BTreeSet::from([0x202, 0x206]));
+        assert_eq!(listing.items.len(), 5);
+        assert_eq!(
+            listing.items[2],
+            Item::Data {
+                addr: 0x204,
+                bytes: vec![0xFF, 0x81]
+            }
+        );
+
+        let octo = format_listing(&listing, Syntax::Octo);
+        assert!(octo.contains("\t:call L0206 "));
+        assert!(octo.contains(": L0202\n\tjump L0202 "));
+        let cowgod = format_listing(&listing, Syntax::Cowgod);
+        assert!(cowgod.contains("  CALL L0206\n"));
+    }
+
+    #[test]
+    fn test_jump_vx() {
+        let instr = decode_at(&[0xB3, 0x12], PROG_START, 0);
+        assert_eq!(instr.to_string(), "JP V0, 0x312");
+        assert_eq!(Octo(&instr).to_string(), "jump0 0x312");
+
+        let instr = instr.with_quirks(&Quirks::superchip());
+        assert_eq!(instr.to_string(), "JP V3, 0x312");
+    }
+}
@@ -1,43 +1,82 @@
-use rand::prelude::*;
 use std::error::Error;
+use std::fmt;
 
-#[derive(Debug)]
-enum Src {
-    Reg(usize),
-    Literal(u16),
-    Key,
-    IReg,
+use crate::ops::{decode, Ops, Src};
+use crate::quirks::Quirks;
+
+const FONT_ADDR: usize = 0x50;
+const BIG_FONT_ADDR: usize = 0xA0;
+
+const RAM_SIZE: usize = 0x10000;
+const PLANES: usize = 2;
+
+pub(crate) const LORES: (usize, usize) = (64, 32);
+pub(crate) const HIRES: (usize, usize) = (128, 64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmulatorError {
+    UnknownOpcode {
+        pc: u16,
+        opcode: u16,
+    },
+    StackOverflow {
+        pc: u16,
+    },
+    StackUnderflow {
+        pc: u16,
+    },
+    MemoryOutOfBounds {
+        addr: usize,
+    },
+    ProgramTooLarge {
+        len: usize,
+    },
+    InvalidState {
+        reason: &'static str,
+    },
+    /// The opcode decoded to operands the instruction can't take
+    UnsupportedOperands {
+        pc: u16,
+        opcode: u16,
+    },
 }
 
-#[derive(Debug)]
-#[allow(clippy::upper_case_acronyms)]
-enum Ops {
-    DisplayClear,
-    DisplayUpdate(Src, Src, Src),
-    CallSubRoutine(Src),
-    ReturnSubRoutine,
-    Jump(Src),
-    JumpRelative(Src),
-    JumpEq(Src, Src),
-    JumpNeq(Src, Src),
-    Add(Src, Src, Src),
-    Sub(Src, Src, Src),
-    And(Src, Src, Src),
-    Or(Src, Src, Src),
-    Xor(Src, Src, Src),
-    LShift(Src),
-    RShift(Src),
-    Rand(Src, Src),
-    ReadDelay(Src),
-    GetKey(Src),
-    WriteDelay(Src),
-    WriteSound(Src),
-    GetSprite(Src),
-    BCD(Src),
-    RegDump(Src),
-    RegLoad(Src),
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {:04X} at {:04X}", opcode, pc)
+            }
+            EmulatorError::StackOverflow { pc } => write!(f, "Stack overflow at {:04X}", pc),
+            EmulatorError::StackUnderflow { pc } => write!(f, "Stack underflow at {:04X}", pc),
+            EmulatorError::MemoryOutOfBounds { addr } => {
+                write!(f, "Memory access out of bounds at {:04X}", addr)
+            }
+            EmulatorError::ProgramTooLarge { len } => {
+                write!(f, "Program of {} bytes does not fit in memory", len)
+            }
+            EmulatorError::InvalidState { reason } => write!(f, "Invalid state: {}", reason),
+            EmulatorError::UnsupportedOperands { pc, opcode } => {
+                write!(f, "Unsupported operands in {:04X} at {:04X}", opcode, pc)
+            }
+        }
+    }
 }
 
+impl Error for EmulatorError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    /// PC points at a zero word, nothing was executed
+    Idle,
+    /// Blocked on the vertical blank or on a key press
+    Waiting,
+    /// The program executed 00FD and will not run any further
+    Exited,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EmulatorState {
     // Going to seperate state out, for use in file io
     pub ram: Vec<u8>, // and anticipating emulator will need extra stuff
@@ -48,18 +87,40 @@ pub struct EmulatorState {
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub register_bank: Vec<u8>,
-    pub display: Vec<bool>,
+    /// One entry per pixel, bit N is set when the pixel is lit on plane N
+    pub display: Vec<u8>,
+    pub display_width: usize,
+    pub display_height: usize,
+    pub plane_mask: u8,
+    pub rpl_flags: Vec<u8>,
+    pub audio_pattern: Vec<u8>,
+    /// F002 has run, so the pattern plays even when it is all zeros
+    pub audio_loaded: bool,
+    pub pitch: u8,
+    pub prev_keys: u16,
+    /// PRNG state for CXNN, saved so replays are exact
+    pub rng: u64,
+}
+
+/// A RAM read or write made by an instruction, fetches are not included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: usize,
+    pub write: bool,
 }
 
 pub struct Emulator {
     state: EmulatorState,
-    rng_state: ThreadRng,
-    prev_keys: u16,
+    quirks: Quirks,
+    vblank_wait: bool,
+    exited: bool,
+    /// Accesses by the last instruction, only kept when enabled
+    accesses: Option<Vec<MemAccess>>,
 }
 
 impl Emulator {
-    pub fn new() -> Self {
-        let mut ram: Vec<u8> = vec![0; 4096];
+    pub fn new(quirks: Quirks) -> Self {
+        let mut ram: Vec<u8> = vec![0; RAM_SIZE];
         let sprites: Vec<u8> = vec![
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
             0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -78,11 +139,31 @@ impl Emulator {
             0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
             0xF0, 0x80, 0xF0, 0x80, 0x80,
         ]; // F
-        ram[0x50..(sprites.len() + 0x50)].copy_from_slice(&sprites[..]);
+        ram[FONT_ADDR..(sprites.len() + FONT_ADDR)].copy_from_slice(&sprites[..]);
+
+        let big_sprites: Vec<u8> = vec![
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
+        ram[BIG_FONT_ADDR..(big_sprites.len() + BIG_FONT_ADDR)].copy_from_slice(&big_sprites[..]);
 
         Self {
             state: EmulatorState {
-                ram: vec![0; 4096],
+                ram,
                 pc: 0,
                 ireg: 0,
                 stack: vec![0; 32],
@@ -90,23 +171,50 @@ impl Emulator {
                 delay_timer: 0,
                 sound_timer: 0,
                 register_bank: vec![0; 16],
-                display: vec![false; 32 * 64],
+                display: vec![0; LORES.0 * LORES.1],
+                display_width: LORES.0,
+                display_height: LORES.1,
+                plane_mask: 0x01,
+                rpl_flags: vec![0; 16],
+                audio_pattern: vec![0; 16],
+                audio_loaded: false,
+                pitch: 64,
+                prev_keys: 0,
+                rng: rand::random(),
             },
-            rng_state: rand::rng(),
-            prev_keys: 0,
+            quirks,
+            vblank_wait: false,
+            exited: false,
+            accesses: None,
         }
     }
 
-    pub fn step(&mut self, keys: u16) {
-        let opcode = self.fetch();
-        if opcode != 0 {
-            let op = self.decode(opcode);
-            self.execute(op, keys);
-            self.prev_keys = keys;
+    pub fn step(&mut self, keys: u16) -> Result<StepOutcome, EmulatorError> {
+        if self.exited {
+            return Ok(StepOutcome::Exited);
         }
+        if self.vblank_wait {
+            return Ok(StepOutcome::Waiting);
+        }
+
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.clear();
+        }
+        let pc = self.state.pc;
+        let opcode = self.fetch()?;
+        if opcode == 0 {
+            return Ok(StepOutcome::Idle);
+        }
+
+        let op = self.decode(pc, opcode)?;
+        let outcome = self.execute(pc, opcode, op, keys)?;
+        self.state.prev_keys = keys;
+
+        Ok(outcome)
     }
 
     pub fn tick(&mut self) {
+        self.vblank_wait = false;
         if self.state.delay_timer > 0 {
             self.state.delay_timer -= 1;
         }
@@ -119,188 +227,264 @@ impl Emulator {
         &self.state
     }
 
-    pub fn load_state(&self, _state: &EmulatorState) -> Result<(), Box<dyn Error>> {
-        todo!();
+    pub fn get_quirks(&self) -> &Quirks {
+        &self.quirks
     }
 
-    pub fn get_prog(&self) -> Vec<u8> {
-        todo!();
+    /// Make CXNN deterministic, the same seed gives the same sequence
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.state.rng = seed;
     }
 
-    pub fn load_prog(&mut self, prog: &[u8]) -> Result<(), Box<dyn Error>> {
+    /// Record the RAM accesses of each instruction, for watchpoints
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.accesses = enabled.then(Vec::new);
+    }
+
+    /// RAM accessed by the last instruction, empty unless logging is enabled
+    pub fn accesses(&self) -> &[MemAccess] {
+        self.accesses.as_deref().unwrap_or(&[])
+    }
+
+    pub fn load_state(&mut self, state: &EmulatorState) -> Result<(), EmulatorError> {
+        if state.ram.len() != self.state.ram.len() {
+            return Err(EmulatorError::InvalidState {
+                reason: "RAM size mismatch",
+            });
+        }
+        if state.register_bank.len() != self.state.register_bank.len() {
+            return Err(EmulatorError::InvalidState {
+                reason: "register count mismatch",
+            });
+        }
+        if ![LORES, HIRES].contains(&(state.display_width, state.display_height))
+            || state.display.len() != state.display_width * state.display_height
+        {
+            return Err(EmulatorError::InvalidState {
+                reason: "display size mismatch",
+            });
+        }
+        if state.rpl_flags.len() != self.state.rpl_flags.len() {
+            return Err(EmulatorError::InvalidState {
+                reason: "flag register count mismatch",
+            });
+        }
+        if state.audio_pattern.len() != self.state.audio_pattern.len() {
+            return Err(EmulatorError::InvalidState {
+                reason: "audio pattern size mismatch",
+            });
+        }
+        if state.stack.len() != self.state.stack.len() {
+            return Err(EmulatorError::InvalidState {
+                reason: "stack size mismatch",
+            });
+        }
+        if state.stack_len > state.stack.len() {
+            return Err(EmulatorError::InvalidState {
+                reason: "stack length exceeds stack size",
+            });
+        }
+
+        self.state = state.clone();
+        self.vblank_wait = false;
+        self.exited = false;
+        Ok(())
+    }
+
+    pub fn load_prog(&mut self, prog: &[u8]) -> Result<(), EmulatorError> {
+        if prog.len() + 512 > self.state.ram.len() {
+            return Err(EmulatorError::ProgramTooLarge { len: prog.len() });
+        }
+
         self.state.ram[512..(prog.len() + 512)].copy_from_slice(prog);
         self.state.pc = 512;
         Ok(())
     }
 
-    fn fetch(&mut self) -> u16 {
-        let opcode: u16 = ((self.state.ram[self.state.pc as usize] as u16) << 8)
-            + (self.state.ram[(self.state.pc as usize) + 1] as u16);
-        if opcode != 0 {
-            self.state.pc += 2;
+    /// How far FX55/FX65 move I with the memory increment quirk
+    fn memory_step(&self, vx: usize) -> u16 {
+        match self.quirks.memory_increment_x {
+            true => vx as u16,
+            false => vx as u16 + 1,
         }
+    }
 
-        opcode
+    /// SplitMix64, its whole state is one word that lives in `EmulatorState`
+    fn random(&mut self) -> u8 {
+        self.state.rng = self.state.rng.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        ((z ^ (z >> 31)) >> 56) as u8
     }
 
-    fn decode(&self, opcode: u16) -> Ops {
-        match (opcode & 0xF000) >> 12 {
-            0x0 => match opcode & 0x0FFF {
-                0x0E0 => Ops::DisplayClear,
-                0x0EE => Ops::ReturnSubRoutine,
-                _ => panic!("Opcode {:04X} not supported", opcode),
-            },
-            0x1 => Ops::Jump(Src::Literal(opcode & 0x0FFF)),
-            0x2 => Ops::CallSubRoutine(Src::Literal(opcode & 0x0FFF)),
-            0x3 => Ops::JumpEq(
-                Src::Reg(((opcode & 0xF00) >> 8) as usize),
-                Src::Literal(opcode & 0x00FF),
-            ),
-            0x4 => Ops::JumpNeq(
-                Src::Reg(((opcode & 0xF00) >> 8) as usize),
-                Src::Literal(opcode & 0x00FF),
-            ),
-            0x5 => Ops::JumpEq(
-                Src::Reg(((opcode & 0xF00) >> 8) as usize),
-                Src::Reg(((opcode & 0x00F0) >> 8) as usize),
-            ),
-            0x6 => Ops::Add(
-                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                Src::Literal(opcode & 0x00FF),
-                Src::Literal(0),
-            ),
-            0x7 => Ops::Add(
-                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                Src::Literal(opcode & 0x00FF),
-            ),
-            0x8 => match opcode & 0x000F {
-                0x0 => Ops::Add(
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x00F0) >> 4) as usize),
-                    Src::Literal(0),
-                ),
-                0x1 => Ops::Or(
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x00F0) >> 4) as usize),
-                ),
-                0x2 => Ops::And(
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x00F0) >> 4) as usize),
-                ),
-                0x3 => Ops::Xor(
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x00F0) >> 4) as usize),
-                ),
-                0x4 => Ops::Add(
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x00F0) >> 4) as usize),
-                ),
-                0x5 => Ops::Sub(
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x00F0) >> 4) as usize),
-                ),
-                0x6 => Ops::RShift(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                0x7 => Ops::Sub(
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                    Src::Reg(((opcode & 0x00F0) >> 4) as usize),
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                ),
-                0xE => Ops::LShift(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                _ => unreachable!(),
-            },
-            0x9 => Ops::JumpNeq(
-                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
-            ),
-            0xA => Ops::Add(Src::IReg, Src::Literal(opcode & 0x0FFF), Src::Literal(0)),
-            0xB => Ops::JumpRelative(Src::Literal(opcode & 0x0FFF)),
-            0xC => Ops::Rand(
-                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                Src::Literal(opcode & 0x00FF),
-            ),
-            0xD => Ops::DisplayUpdate(
-                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
-                Src::Literal(opcode & 0x000F),
-            ),
-            0xE => match opcode & 0x00FF {
-                0x9E => Ops::JumpEq(Src::Key, Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                0xA1 => Ops::JumpNeq(Src::Key, Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                _ => unreachable!(),
-            },
-            0xF => match opcode & 0x00FF {
-                0x07 => Ops::ReadDelay(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                0x0A => Ops::GetKey(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                0x15 => Ops::WriteDelay(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                0x18 => Ops::WriteSound(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                0x1E => Ops::Add(
-                    Src::IReg,
-                    Src::IReg,
-                    Src::Reg(((opcode & 0x0F00) >> 8) as usize),
-                ),
-                0x29 => Ops::GetSprite(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                0x33 => Ops::BCD(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                0x55 => Ops::RegDump(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                0x65 => Ops::RegLoad(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
-                _ => unreachable!(),
-            },
-            _ => unreachable!(),
+    fn set_resolution(&mut self, (width, height): (usize, usize)) {
+        self.state.display_width = width;
+        self.state.display_height = height;
+        self.state.display = vec![0; width * height];
+    }
+
+    /// Move the selected planes by (dx, dy) pixels, filling with blanks
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.state.display_width, self.state.display_height);
+        let mask = self.state.plane_mask;
+        let old = self.state.display.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x as isize - dx, y as isize - dy);
+                let src = if sx >= 0 && sy >= 0 && (sx as usize) < width && (sy as usize) < height {
+                    old[(sy as usize) * width + (sx as usize)] & mask
+                } else {
+                    0
+                };
+                let idx = y * width + x;
+                self.state.display[idx] = (old[idx] & !mask) | src;
+            }
+        }
+    }
+
+    /// Skip the next instruction, which is four bytes long if it is F000 NNNN
+    fn skip(&mut self) -> Result<(), EmulatorError> {
+        let pc = self.state.pc as usize;
+        let next = ((self.read_ram(pc)? as u16) << 8) + (self.read_ram(pc + 1)? as u16);
+        let len = if next == 0xF000 { 4 } else { 2 };
+        self.state.pc = self.state.pc.wrapping_add(len);
+        Ok(())
+    }
+
+    fn read_ram(&self, addr: usize) -> Result<u8, EmulatorError> {
+        match self.state.ram.get(addr) {
+            Some(val) => Ok(*val),
+            None => Err(EmulatorError::MemoryOutOfBounds { addr }),
+        }
+    }
+
+    /// Read on behalf of an instruction, as opposed to fetching one
+    fn read_data(&mut self, addr: usize) -> Result<u8, EmulatorError> {
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.push(MemAccess { addr, write: false });
+        }
+        self.read_ram(addr)
+    }
+
+    fn write_ram(&mut self, addr: usize, val: u8) -> Result<(), EmulatorError> {
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.push(MemAccess { addr, write: true });
+        }
+        match self.state.ram.get_mut(addr) {
+            Some(byte) => {
+                *byte = val;
+                Ok(())
+            }
+            None => Err(EmulatorError::MemoryOutOfBounds { addr }),
+        }
+    }
+
+    fn fetch(&mut self) -> Result<u16, EmulatorError> {
+        let pc = self.state.pc as usize;
+        let opcode: u16 = ((self.read_ram(pc)? as u16) << 8) + (self.read_ram(pc + 1)? as u16);
+        if opcode != 0 {
+            self.state.pc = self.state.pc.wrapping_add(2);
         }
+
+        Ok(opcode)
+    }
+
+    fn decode(&self, pc: u16, opcode: u16) -> Result<Ops, EmulatorError> {
+        // F000 NNNN is the only instruction that reads past its own opcode
+        let next = match opcode {
+            0xF000 => {
+                let addr = (pc as usize) + 2;
+                ((self.read_ram(addr)? as u16) << 8) + (self.read_ram(addr + 1)? as u16)
+            }
+            _ => 0,
+        };
+
+        decode(opcode, next).ok_or(EmulatorError::UnknownOpcode { pc, opcode })
     }
 
-    fn execute(&mut self, op: Ops, keys: u16) {
+    fn execute(
+        &mut self,
+        pc: u16,
+        opcode: u16,
+        op: Ops,
+        keys: u16,
+    ) -> Result<StepOutcome, EmulatorError> {
+        let unsupported = EmulatorError::UnsupportedOperands { pc, opcode };
         match op {
             Ops::DisplayClear => {
-                for i in 0..self.state.display.len() {
-                    self.state.display[i] = false;
+                let mask = self.state.plane_mask;
+                for px in self.state.display.iter_mut() {
+                    *px &= !mask;
                 }
             }
             Ops::DisplayUpdate(Src::Reg(vx), Src::Reg(vy), Src::Literal(n)) => {
-                let px = (self.state.register_bank[vx] & 0x3F) as usize;
-                let py = (self.state.register_bank[vy] & 0x1F) as usize;
+                let (width, height) = (self.state.display_width, self.state.display_height);
+                let px = (self.state.register_bank[vx] as usize) % width;
+                let py = (self.state.register_bank[vy] as usize) % height;
                 self.state.register_bank[15] = 0;
 
-                for offset in 0..(n as usize) {
-                    if py + offset > 31 {
-                        break;
+                // DXY0 draws a 16x16 sprite stored as two bytes per row
+                let (rows, cols) = match n {
+                    0 => (16, 16),
+                    _ => (n as usize, 8),
+                };
+                let row_bytes = cols / 8;
+
+                // Each selected plane consumes its own copy of the sprite data
+                let mut addr = self.state.ireg as usize;
+                for plane in 0..PLANES {
+                    let bit_mask = 1 << plane;
+                    if self.state.plane_mask & bit_mask == 0 {
+                        continue;
                     }
-                    let byte = self.state.ram[(self.state.ireg as usize) + offset];
-                    for bit in 0..8 {
-                        if px + (bit as usize) > 63 {
+
+                    for offset in 0..rows {
+                        if self.quirks.clip && py + offset >= height {
                             break;
                         }
-                        if byte & (0x80 >> bit) > 0 {
-                            let idx = (py + offset) * 64 + px + (bit as usize);
-                            if self.state.display[idx] {
-                                self.state.display[idx] = false;
-                                self.state.register_bank[15] = 1;
-                            } else {
-                                self.state.display[idx] = true;
+                        let mut line: u16 = 0;
+                        for b in 0..row_bytes {
+                            line = (line << 8)
+                                | (self.read_data(addr + offset * row_bytes + b)? as u16);
+                        }
+                        for bit in 0..cols {
+                            if self.quirks.clip && px + bit >= width {
+                                break;
+                            }
+                            if line & (1 << (cols - 1 - bit)) > 0 {
+                                let idx = ((py + offset) % height) * width + (px + bit) % width;
+                                if self.state.display[idx] & bit_mask > 0 {
+                                    self.state.register_bank[15] = 1;
+                                }
+                                self.state.display[idx] ^= bit_mask;
                             }
                         }
                     }
+                    addr += rows * row_bytes;
                 }
+
+                self.vblank_wait = self.quirks.display_wait && (width, height) == LORES;
             }
-            Ops::DisplayUpdate(_, _, _) => panic!("Unsupported Display Update: {:?}", op),
+            Ops::DisplayUpdate(_, _, _) => return Err(unsupported),
             Ops::CallSubRoutine(Src::Literal(n)) => {
                 if self.state.stack_len < 16 {
                     self.state.stack[self.state.stack_len] = self.state.pc;
                     self.state.stack_len += 1;
                     self.state.pc = n;
                 } else {
-                    panic!("Stack Overflow");
+                    return Err(EmulatorError::StackOverflow {
+                        pc: self.state.pc.wrapping_sub(2),
+                    });
                 }
             }
-            Ops::CallSubRoutine(_) => panic!("Unsuppoorted CallSubRoutine: {:?}", op),
+            Ops::CallSubRoutine(_) => return Err(unsupported),
             Ops::ReturnSubRoutine => {
                 if self.state.stack_len == 0 {
-                    panic!("Stack Underflow");
+                    return Err(EmulatorError::StackUnderflow {
+                        pc: self.state.pc.wrapping_sub(2),
+                    });
                 } else {
                     self.state.pc = self.state.stack[self.state.stack_len - 1];
                     self.state.stack_len -= 1;
@@ -309,48 +493,55 @@ impl Emulator {
             Ops::Jump(Src::Literal(n)) => {
                 self.state.pc = n;
             }
-            Ops::Jump(_) => panic!("Unsupported Jump: {:?}", op),
+            Ops::Jump(_) => return Err(unsupported),
             Ops::JumpRelative(Src::Literal(n)) => {
-                self.state.pc = (self.state.register_bank[0] as u16) + n;
+                let vx = match self.quirks.jump_vx {
+                    true => ((n & 0x0F00) >> 8) as usize,
+                    false => 0,
+                };
+                self.state.pc = (self.state.register_bank[vx] as u16) + n;
             }
-            Ops::JumpRelative(_) => panic!("Unsupported JumpRelative: {:?}", op),
+            Ops::JumpRelative(_) => return Err(unsupported),
             Ops::JumpEq(Src::Reg(vx), Src::Literal(n)) => {
                 if self.state.register_bank[vx] == (n as u8) {
-                    self.state.pc += 2;
+                    self.skip()?;
                 }
             }
             Ops::JumpEq(Src::Reg(vx), Src::Reg(vy)) => {
                 if self.state.register_bank[vx] == self.state.register_bank[vy] {
-                    self.state.pc += 2;
+                    self.skip()?;
                 }
             }
             Ops::JumpEq(Src::Key, Src::Reg(vx)) => {
                 if keys & (1 << ((self.state.register_bank[vx] as u16) & 0x000F)) > 0 {
-                    self.state.pc += 2;
+                    self.skip()?;
                 }
             }
-            Ops::JumpEq(_, _) => panic!("Unsupported JumpEq: {:?}", op),
+            Ops::JumpEq(_, _) => return Err(unsupported),
             Ops::JumpNeq(Src::Reg(vx), Src::Literal(n)) => {
                 if self.state.register_bank[vx] != (n as u8) {
-                    self.state.pc += 2;
+                    self.skip()?;
                 }
             }
             Ops::JumpNeq(Src::Reg(vx), Src::Reg(vy)) => {
                 if self.state.register_bank[vx] != self.state.register_bank[vy] {
-                    self.state.pc += 2;
+                    self.skip()?;
                 }
             }
             Ops::JumpNeq(Src::Key, Src::Reg(vx)) => {
                 if keys & (1 << ((self.state.register_bank[vx] as u16) & 0x000F)) == 0 {
-                    self.state.pc += 2;
+                    self.skip()?;
                 }
             }
-            Ops::JumpNeq(_, _) => panic!("Unsupported JumpNeq: {:?}", op),
+            Ops::JumpNeq(_, _) => return Err(unsupported),
             Ops::Add(Src::IReg, Src::Literal(a), Src::Literal(b)) => {
                 self.state.ireg = a + b;
             }
             Ops::Add(Src::IReg, Src::IReg, Src::Reg(vx)) => {
-                self.state.ireg += self.state.register_bank[vx] as u16;
+                self.state.ireg = self
+                    .state
+                    .ireg
+                    .wrapping_add(self.state.register_bank[vx] as u16);
             }
             Ops::Add(Src::Reg(vx), Src::Literal(a), Src::Literal(b)) => {
                 self.state.register_bank[vx] = ((a + b) % 256) as u8;
@@ -365,7 +556,7 @@ impl Emulator {
                 self.state.register_bank[15] = ((res & 0x0100) >> 8) as u8;
                 self.state.register_bank[vd] = (res & 0x00FF) as u8;
             }
-            Ops::Add(_, _, _) => panic!("Unsupported Add: {:?}", op),
+            Ops::Add(_, _, _) => return Err(unsupported),
             Ops::Sub(Src::Reg(vd), Src::Reg(vx), Src::Reg(vy)) => {
                 if self.state.register_bank[vy] > self.state.register_bank[vx] {
                     self.state.register_bank[15] = 0;
@@ -377,98 +568,419 @@ impl Emulator {
                         self.state.register_bank[vx] - self.state.register_bank[vy];
                 }
             }
-            Ops::Sub(_, _, _) => panic!("Unsupported Sub: {:?}", op),
+            Ops::Sub(_, _, _) => return Err(unsupported),
             Ops::Or(Src::Reg(vd), Src::Reg(vx), Src::Reg(vy)) => {
                 self.state.register_bank[vd] =
                     self.state.register_bank[vx] | self.state.register_bank[vy];
+                if self.quirks.vf_reset {
+                    self.state.register_bank[15] = 0;
+                }
             }
-            Ops::Or(_, _, _) => panic!("Unsupported BitOr: {:?}", op),
+            Ops::Or(_, _, _) => return Err(unsupported),
             Ops::And(Src::Reg(vd), Src::Reg(vx), Src::Reg(vy)) => {
                 self.state.register_bank[vd] =
                     self.state.register_bank[vx] & self.state.register_bank[vy];
+                if self.quirks.vf_reset {
+                    self.state.register_bank[15] = 0;
+                }
             }
-            Ops::And(_, _, _) => panic!("Unsupported BitAnd: {:?}", op),
+            Ops::And(_, _, _) => return Err(unsupported),
             Ops::Xor(Src::Reg(vd), Src::Reg(vx), Src::Reg(vy)) => {
                 self.state.register_bank[vd] =
                     self.state.register_bank[vx] ^ self.state.register_bank[vy];
-            }
-            Ops::Xor(_, _, _) => panic!("Unsupported BitXor: {:?}", op),
-            Ops::RShift(Src::Reg(vx)) => {
-                if self.state.register_bank[vx] & 0x01 > 0 {
-                    self.state.register_bank[15] = 1;
-                }
-                self.state.register_bank[vx] = (self.state.register_bank[vx] & 0xFE) >> 1;
-            }
-            Ops::RShift(_) => panic!("Unsupported LShift: {:?}", op),
-            Ops::LShift(Src::Reg(vx)) => {
-                if self.state.register_bank[vx] & 0x80 > 0 {
-                    self.state.register_bank[15] = 1;
+                if self.quirks.vf_reset {
+                    self.state.register_bank[15] = 0;
                 }
-                self.state.register_bank[vx] = (self.state.register_bank[vx] & 0x7F) << 1;
             }
-            Ops::LShift(_) => panic!("Unsupported LShift: {:?}", op),
+            Ops::Xor(_, _, _) => return Err(unsupported),
+            Ops::RShift(Src::Reg(vx), Src::Reg(vy)) => {
+                let val = match self.quirks.shift {
+                    true => self.state.register_bank[vx],
+                    false => self.state.register_bank[vy],
+                };
+                self.state.register_bank[vx] = val >> 1;
+                self.state.register_bank[15] = val & 0x01;
+            }
+            Ops::RShift(_, _) => return Err(unsupported),
+            Ops::LShift(Src::Reg(vx), Src::Reg(vy)) => {
+                let val = match self.quirks.shift {
+                    true => self.state.register_bank[vx],
+                    false => self.state.register_bank[vy],
+                };
+                self.state.register_bank[vx] = val << 1;
+                self.state.register_bank[15] = (val & 0x80) >> 7;
+            }
+            Ops::LShift(_, _) => return Err(unsupported),
             Ops::Rand(Src::Reg(vx), Src::Literal(n)) => {
-                self.state.register_bank[vx] = self.rng_state.random::<u8>() & (n as u8);
+                self.state.register_bank[vx] = self.random() & (n as u8);
             }
-            Ops::Rand(_, _) => panic!("Unsupported Rand: {:?}", op),
+            Ops::Rand(_, _) => return Err(unsupported),
             Ops::ReadDelay(Src::Reg(vx)) => {
                 self.state.register_bank[vx] = self.state.delay_timer;
             }
-            Ops::ReadDelay(_) => panic!("Unsupported GetDelay: {:?}", op),
+            Ops::ReadDelay(_) => return Err(unsupported),
             Ops::GetKey(Src::Reg(vx)) => {
-                if keys != self.prev_keys {
+                if keys != self.state.prev_keys {
                     for i in 0..16 {
-                        if (keys ^ self.prev_keys) & (1 << i) > 0 {
+                        if (keys ^ self.state.prev_keys) & (1 << i) > 0 {
                             self.state.register_bank[vx] = i as u8;
                             break;
                         }
                     }
                 } else {
-                    self.state.pc -= 1;
+                    self.state.pc = self.state.pc.wrapping_sub(2);
+                    return Ok(StepOutcome::Waiting);
                 }
             }
-            Ops::GetKey(_) => panic!("Unsupported GetKey: {:?}", op),
+            Ops::GetKey(_) => return Err(unsupported),
             Ops::WriteDelay(Src::Reg(vx)) => {
                 self.state.delay_timer = self.state.register_bank[vx];
             }
-            Ops::WriteDelay(_) => panic!("Unsupported WriteDelay: {:?}", op),
+            Ops::WriteDelay(_) => return Err(unsupported),
             Ops::WriteSound(Src::Reg(vx)) => {
                 self.state.sound_timer = self.state.register_bank[vx];
             }
-            Ops::WriteSound(_) => panic!("Unsupported WriteSound: {:?}", op),
+            Ops::WriteSound(_) => return Err(unsupported),
             Ops::GetSprite(Src::Reg(vx)) => {
-                self.state.ireg = 0x0050 + (0x0005 * (self.state.register_bank[vx] as u16));
+                self.state.ireg =
+                    (FONT_ADDR as u16) + (0x0005 * ((self.state.register_bank[vx] & 0x0F) as u16));
             }
-            Ops::GetSprite(_) => panic!("Upsupported GetSprite: {:?}", op),
+            Ops::GetSprite(_) => return Err(unsupported),
             Ops::BCD(Src::Reg(vx)) => {
+                let addr = self.state.ireg as usize;
                 let mut val = self.state.register_bank[vx];
-                self.state.ram[(self.state.ireg + 2) as usize] = val % 10;
+                self.write_ram(addr + 2, val % 10)?;
                 val /= 10;
-                self.state.ram[(self.state.ireg + 1) as usize] = val % 10;
+                self.write_ram(addr + 1, val % 10)?;
                 val /= 10;
-                self.state.ram[self.state.ireg as usize] = val % 10;
+                self.write_ram(addr, val % 10)?;
             }
-            Ops::BCD(_) => panic!("Upsupported BCD: {:?}", op),
+            Ops::BCD(_) => return Err(unsupported),
             Ops::RegDump(Src::Reg(vx)) => {
                 for i in 0..=vx {
-                    self.state.ram[(self.state.ireg + i as u16) as usize] =
-                        self.state.register_bank[i];
+                    self.write_ram(self.state.ireg as usize + i, self.state.register_bank[i])?;
+                }
+                if self.quirks.memory_increment {
+                    self.state.ireg = self.state.ireg.wrapping_add(self.memory_step(vx));
                 }
             }
-            Ops::RegDump(_) => panic!("Unsupported RegDump: {:?}", op),
+            Ops::RegDump(_) => return Err(unsupported),
             Ops::RegLoad(Src::Reg(vx)) => {
                 for i in 0..=vx {
-                    self.state.register_bank[i] =
-                        self.state.ram[(self.state.ireg + i as u16) as usize];
+                    self.state.register_bank[i] = self.read_data(self.state.ireg as usize + i)?;
+                }
+                if self.quirks.memory_increment {
+                    self.state.ireg = self.state.ireg.wrapping_add(self.memory_step(vx));
+                }
+            }
+            Ops::RegLoad(_) => return Err(unsupported),
+            Ops::ScrollDown(Src::Literal(n)) => self.scroll(0, n as isize),
+            Ops::ScrollDown(_) => return Err(unsupported),
+            Ops::ScrollUp(Src::Literal(n)) => self.scroll(0, -(n as isize)),
+            Ops::ScrollUp(_) => return Err(unsupported),
+            Ops::ScrollRight => self.scroll(4, 0),
+            Ops::ScrollLeft => self.scroll(-4, 0),
+            Ops::LowRes => self.set_resolution(LORES),
+            Ops::HighRes => self.set_resolution(HIRES),
+            Ops::Exit => {
+                self.exited = true;
+                return Ok(StepOutcome::Exited);
+            }
+            Ops::GetBigSprite(Src::Reg(vx)) => {
+                self.state.ireg =
+                    (BIG_FONT_ADDR as u16) + (10 * ((self.state.register_bank[vx] & 0x0F) as u16));
+            }
+            Ops::GetBigSprite(_) => return Err(unsupported),
+            Ops::FlagsDump(Src::Reg(vx)) => {
+                self.state.rpl_flags[..=vx].copy_from_slice(&self.state.register_bank[..=vx]);
+            }
+            Ops::FlagsDump(_) => return Err(unsupported),
+            Ops::FlagsLoad(Src::Reg(vx)) => {
+                self.state.register_bank[..=vx].copy_from_slice(&self.state.rpl_flags[..=vx]);
+            }
+            Ops::FlagsLoad(_) => return Err(unsupported),
+            Ops::RangeDump(Src::Reg(vx), Src::Reg(vy)) => {
+                let regs: Vec<usize> = match vx <= vy {
+                    true => (vx..=vy).collect(),
+                    false => (vy..=vx).rev().collect(),
+                };
+                for (i, reg) in regs.into_iter().enumerate() {
+                    self.write_ram(self.state.ireg as usize + i, self.state.register_bank[reg])?;
+                }
+            }
+            Ops::RangeDump(_, _) => return Err(unsupported),
+            Ops::RangeLoad(Src::Reg(vx), Src::Reg(vy)) => {
+                let regs: Vec<usize> = match vx <= vy {
+                    true => (vx..=vy).collect(),
+                    false => (vy..=vx).rev().collect(),
+                };
+                for (i, reg) in regs.into_iter().enumerate() {
+                    self.state.register_bank[reg] = self.read_data(self.state.ireg as usize + i)?;
                 }
             }
-            Ops::RegLoad(_) => panic!("Unsupported RegLoad: {:?}", op),
+            Ops::RangeLoad(_, _) => return Err(unsupported),
+            Ops::LongLoad(Src::Literal(n)) => {
+                self.state.ireg = n;
+                self.state.pc = self.state.pc.wrapping_add(2);
+            }
+            Ops::LongLoad(_) => return Err(unsupported),
+            Ops::SelectPlanes(Src::Literal(n)) => {
+                self.state.plane_mask = n as u8;
+            }
+            Ops::SelectPlanes(_) => return Err(unsupported),
+            Ops::LoadAudio => {
+                for i in 0..self.state.audio_pattern.len() {
+                    self.state.audio_pattern[i] = self.read_data(self.state.ireg as usize + i)?;
+                }
+                self.state.audio_loaded = true;
+            }
+            Ops::SetPitch(Src::Reg(vx)) => {
+                self.state.pitch = self.state.register_bank[vx];
+            }
+            Ops::SetPitch(_) => return Err(unsupported),
         }
+
+        Ok(StepOutcome::Executed)
     }
 }
 
 impl Default for Emulator {
     fn default() -> Self {
-        Self::new()
+        Self::new(Quirks::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shift_quirk() {
+        // V1 = 0x81, V2 = 0x03, V1 >>= V2
+        let prog = [0x61, 0x81, 0x62, 0x03, 0x81, 0x26];
+
+        let mut ch8 = Emulator::new(Quirks::default());
+        ch8.load_prog(&prog).unwrap();
+        for _ in 0..3 {
+            ch8.step(0).unwrap();
+        }
+        assert_eq!(ch8.get_state().register_bank[1], 0x40);
+        assert_eq!(ch8.get_state().register_bank[15], 1);
+
+        let mut ch8 = Emulator::new(Quirks::cosmac_vip());
+        ch8.load_prog(&prog).unwrap();
+        for _ in 0..3 {
+            ch8.step(0).unwrap();
+        }
+        assert_eq!(ch8.get_state().register_bank[1], 0x01);
+        assert_eq!(ch8.get_state().register_bank[15], 1);
+    }
+
+    #[test]
+    fn test_memory_increment_quirk() {
+        // I = 0x300, dump V0..V3
+        let prog = [0xA3, 0x00, 0xF3, 0x55];
+
+        let mut ch8 = Emulator::new(Quirks::default());
+        ch8.load_prog(&prog).unwrap();
+        ch8.step(0).unwrap();
+        ch8.step(0).unwrap();
+        assert_eq!(ch8.get_state().ireg, 0x300);
+
+        let mut ch8 = Emulator::new(Quirks::cosmac_vip());
+        ch8.load_prog(&prog).unwrap();
+        ch8.step(0).unwrap();
+        ch8.step(0).unwrap();
+        assert_eq!(ch8.get_state().ireg, 0x304);
+
+        let mut ch8 = Emulator::new(Quirks::chip48());
+        ch8.load_prog(&prog).unwrap();
+        ch8.step(0).unwrap();
+        ch8.step(0).unwrap();
+        assert_eq!(ch8.get_state().ireg, 0x303);
+    }
+
+    #[test]
+    fn test_display_wait_quirk() {
+        // Draw twice in low resolution, then twice in high resolution
+        let prog = [0xD0, 0x01, 0xD0, 0x01, 0x00, 0xFF, 0xD0, 0x01, 0xD0, 0x01];
+
+        let mut ch8 = Emulator::new(Quirks::superchip());
+        ch8.load_prog(&prog).unwrap();
+        assert_eq!(ch8.step(0), Ok(StepOutcome::Executed));
+        assert_eq!(ch8.step(0), Ok(StepOutcome::Waiting));
+        ch8.tick();
+        assert_eq!(ch8.step(0), Ok(StepOutcome::Executed));
+        ch8.tick();
+        for _ in 0..3 {
+            assert_eq!(ch8.step(0), Ok(StepOutcome::Executed));
+        }
+
+        let mut ch8 = Emulator::new(Quirks::chip48());
+        ch8.load_prog(&prog).unwrap();
+        for _ in 0..5 {
+            assert_eq!(ch8.step(0), Ok(StepOutcome::Executed));
+        }
+    }
+
+    #[test]
+    fn test_seeded_rng() {
+        // V0..V3 = random 0xFF, then loop back
+        let prog = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF, 0xC3, 0xFF, 0x12, 0x00];
+        let run = |ch8: &mut Emulator| {
+            for _ in 0..5 {
+                ch8.step(0).unwrap();
+            }
+            ch8.get_state().register_bank[..4].to_vec()
+        };
+
+        let mut a = Emulator::default();
+        let mut b = Emulator::default();
+        a.load_prog(&prog).unwrap();
+        b.load_prog(&prog).unwrap();
+        a.seed_rng(42);
+        b.seed_rng(42);
+        let first = run(&mut a);
+        assert_eq!(first, run(&mut b));
+        assert!(first.iter().any(|v| *v != first[0]));
+
+        // Restoring a state replays the same numbers
+        let saved = a.get_state().clone();
+        let next = run(&mut a);
+        assert_ne!(next, first);
+        b.load_state(&saved).unwrap();
+        assert_eq!(run(&mut b), next);
+    }
+
+    #[test]
+    fn test_step_errors() {
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&[0x00, 0xEE]).unwrap();
+        assert_eq!(
+            ch8.step(0),
+            Err(EmulatorError::StackUnderflow { pc: 0x200 })
+        );
+
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&[0x8A, 0xBF]).unwrap();
+        assert_eq!(
+            ch8.step(0),
+            Err(EmulatorError::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0x8ABF
+            })
+        );
+
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&[0xF0, 0x00, 0xFF, 0xFF, 0xF0, 0x33])
+            .unwrap();
+        assert_eq!(ch8.step(0), Ok(StepOutcome::Executed));
+        assert_eq!(
+            ch8.step(0),
+            Err(EmulatorError::MemoryOutOfBounds { addr: 0x10001 })
+        );
+
+        // decode never builds this, but execute must not panic on it
+        let op = Ops::DisplayUpdate(Src::Key, Src::IReg, Src::Literal(0));
+        assert_eq!(
+            Emulator::default().execute(0x200, 0xD000, op, 0),
+            Err(EmulatorError::UnsupportedOperands {
+                pc: 0x200,
+                opcode: 0xD000
+            })
+        );
+    }
+
+    #[test]
+    fn test_load_state_sizes() {
+        let mut ch8 = Emulator::default();
+        let mut state = ch8.get_state().clone();
+        state.stack.truncate(1);
+        assert_eq!(
+            ch8.load_state(&state),
+            Err(EmulatorError::InvalidState {
+                reason: "stack size mismatch"
+            })
+        );
+    }
+
+    #[test]
+    fn test_top_of_memory() {
+        let at = |pc: u16, ireg: u16, prog: [u8; 2]| {
+            let mut ch8 = Emulator::new(Quirks::xochip());
+            let mut state = ch8.get_state().clone();
+            state.pc = pc;
+            state.ireg = ireg;
+            state.ram[pc as usize..pc as usize + 2].copy_from_slice(&prog);
+            ch8.load_state(&state).unwrap();
+            ch8
+        };
+
+        // I wraps past the end after FX55, running off the end is an error
+        let mut ch8 = at(0x200, 0xFFFF, [0xF0, 0x55]);
+        assert_eq!(ch8.step(0), Ok(StepOutcome::Executed));
+        assert_eq!(ch8.get_state().ireg, 0);
+        let mut ch8 = at(0x200, 0xFFFF, [0xF1, 0x65]);
+        assert_eq!(
+            ch8.step(0),
+            Err(EmulatorError::MemoryOutOfBounds { addr: 0x10000 })
+        );
+
+        let mut ch8 = at(0xFFFE, 0, [0x00, 0xEE]);
+        assert_eq!(
+            ch8.step(0),
+            Err(EmulatorError::StackUnderflow { pc: 0xFFFE })
+        );
+        let mut ch8 = at(0xFFFE, 0, [0xF0, 0x0A]);
+        assert_eq!(ch8.step(0), Ok(StepOutcome::Waiting));
+        assert_eq!(ch8.get_state().pc, 0xFFFE);
+    }
+
+    #[test]
+    fn test_superchip_display() {
+        // hires, draw the big "8" at (0, 0), scroll down 2 and right 4
+        let prog = [
+            0x00, 0xFF, 0x60, 0x08, 0xF0, 0x30, 0xD1, 0x1A, 0x00, 0xC2, 0x00, 0xFB,
+        ];
+
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&prog).unwrap();
+        for _ in 0..6 {
+            ch8.step(0).unwrap();
+        }
+
+        let state = ch8.get_state();
+        assert_eq!((state.display_width, state.display_height), (128, 64));
+        assert_eq!(state.display.len(), 128 * 64);
+        assert_eq!(state.display[128 + 4], 0);
+        assert_eq!(state.display[2 * 128 + 4], 1);
+        assert_eq!(state.display[2 * 128 + 11], 1);
+        assert_eq!(state.display[2 * 128 + 12], 0);
+
+        ch8.load_prog(&[0x00, 0xFD]).unwrap();
+        assert_eq!(ch8.step(0), Ok(StepOutcome::Exited));
+        assert_eq!(ch8.step(0), Ok(StepOutcome::Exited));
+    }
+
+    #[test]
+    fn test_xochip_planes() {
+        // select both planes, I = 0x0210, draw a 1 row sprite with a
+        // different byte per plane, then clear plane 1 only
+        let prog = [
+            0xF3, 0x01, 0xF0, 0x00, 0x02, 0x10, 0xD0, 0x01, 0xF1, 0x01, 0x00, 0xE0, 0x00, 0x00,
+            0x00, 0x00, 0xF0, 0x0F,
+        ];
+
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&prog).unwrap();
+        for _ in 0..4 {
+            ch8.step(0).unwrap();
+        }
+        assert_eq!(ch8.get_state().ireg, 0x0210);
+        assert_eq!(ch8.get_state().display[..8], [1, 1, 1, 1, 2, 2, 2, 2]);
+
+        ch8.step(0).unwrap();
+        ch8.step(0).unwrap();
+        assert_eq!(ch8.get_state().display[..8], [0, 0, 0, 0, 2, 2, 2, 2]);
     }
 }
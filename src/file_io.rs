@@ -1,21 +1,230 @@
-use crate::emulator::EmulatorState;
+use crate::emulator::{EmulatorState, HIRES, LORES};
 use std::error::Error;
 use std::fs;
+use std::path::Path;
+
+const STATE_MAGIC: &[u8; 4] = b"CH8S";
+const STATE_VERSION: u16 = 1;
+const STATE_PLANES: u8 = 2;
 
 pub fn read_program(fname: &str) -> Result<Vec<u8>, Box<dyn Error>> {
     Ok(fs::read(fname)?)
 }
 
-pub fn write_program(_fname: &str, _prog: &[u8]) {
-    todo!()
+pub fn write_program(fname: &str, prog: &[u8]) -> Result<(), Box<dyn Error>> {
+    Ok(fs::write(fname, prog)?)
+}
+
+pub fn read_state(fname: &str) -> Result<EmulatorState, Box<dyn Error>> {
+    decode_state(&fs::read(fname)?)
+}
+
+pub fn write_state(fname: &str, state: &EmulatorState) -> Result<(), Box<dyn Error>> {
+    Ok(fs::write(fname, encode_state(state))?)
 }
 
-pub fn read_state(_fname: &str) -> EmulatorState {
-    todo!()
+/// Path of a numbered save slot, stored next to the program it belongs to
+/// or in `save_dir` when one is set
+pub fn state_slot_path(program: Option<&str>, save_dir: Option<&str>, slot: u8) -> String {
+    let path = format!("{}.s{}", program.unwrap_or("chip-8"), slot);
+    match save_dir {
+        Some(dir) => Path::new(dir)
+            .join(Path::new(&path).file_name().unwrap())
+            .to_string_lossy()
+            .into_owned(),
+        None => path,
+    }
 }
 
-pub fn write_state(_fname: &str, _state: &EmulatorState) {
-    todo!()
+// Save state layout, all integers little endian:
+//   magic "CH8S", version u16
+//   ram: len u32, bytes
+//   pc u16, ireg u16
+//   stack: len u16, entries u16 each, stack_len u16
+//   delay_timer u8, sound_timer u8
+//   registers: len u8, bytes
+//   display: width u16, height u16, planes u8, then for each plane the
+//            pixels packed 8 per byte MSB first
+//   plane_mask u8
+//   rpl flags: len u8, bytes
+//   audio pattern: len u8, bytes, loaded u8
+//   pitch u8
+//   prev_keys u16
+//   rng u64
+pub fn encode_state(state: &EmulatorState) -> Vec<u8> {
+    let mut buf: Vec<u8> = Vec::new();
+    buf.extend_from_slice(STATE_MAGIC);
+    buf.extend_from_slice(&STATE_VERSION.to_le_bytes());
+
+    buf.extend_from_slice(&(state.ram.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&state.ram);
+
+    buf.extend_from_slice(&state.pc.to_le_bytes());
+    buf.extend_from_slice(&state.ireg.to_le_bytes());
+
+    buf.extend_from_slice(&(state.stack.len() as u16).to_le_bytes());
+    for val in state.stack.iter() {
+        buf.extend_from_slice(&val.to_le_bytes());
+    }
+    buf.extend_from_slice(&(state.stack_len as u16).to_le_bytes());
+
+    buf.push(state.delay_timer);
+    buf.push(state.sound_timer);
+
+    buf.push(state.register_bank.len() as u8);
+    buf.extend_from_slice(&state.register_bank);
+
+    buf.extend_from_slice(&(state.display_width as u16).to_le_bytes());
+    buf.extend_from_slice(&(state.display_height as u16).to_le_bytes());
+    buf.push(STATE_PLANES);
+    for plane in 0..STATE_PLANES {
+        for chunk in state.display.chunks(8) {
+            let mut byte = 0;
+            for (bit, px) in chunk.iter().enumerate() {
+                if px & (1 << plane) > 0 {
+                    byte |= 0x80 >> bit;
+                }
+            }
+            buf.push(byte);
+        }
+    }
+    buf.push(state.plane_mask);
+
+    buf.push(state.rpl_flags.len() as u8);
+    buf.extend_from_slice(&state.rpl_flags);
+
+    buf.push(state.audio_pattern.len() as u8);
+    buf.extend_from_slice(&state.audio_pattern);
+    buf.push(state.audio_loaded as u8);
+    buf.push(state.pitch);
+
+    buf.extend_from_slice(&state.prev_keys.to_le_bytes());
+    buf.extend_from_slice(&state.rng.to_le_bytes());
+
+    buf
+}
+
+pub fn decode_state(buf: &[u8]) -> Result<EmulatorState, Box<dyn Error>> {
+    let mut rd = StateReader { buf, pos: 0 };
+
+    if rd.bytes(4)? != STATE_MAGIC {
+        return Err("not a chip-8 save state".into());
+    }
+    let version = rd.u16()?;
+    if version != STATE_VERSION {
+        return Err(format!("unsupported save state version {}", version).into());
+    }
+
+    let ram_len = rd.u32()? as usize;
+    let ram = rd.bytes(ram_len)?.to_vec();
+
+    let pc = rd.u16()?;
+    let ireg = rd.u16()?;
+
+    let stack_size = rd.u16()? as usize;
+    let mut stack = Vec::with_capacity(stack_size);
+    for _ in 0..stack_size {
+        stack.push(rd.u16()?);
+    }
+    let stack_len = rd.u16()? as usize;
+
+    let delay_timer = rd.u8()?;
+    let sound_timer = rd.u8()?;
+
+    let reg_len = rd.u8()? as usize;
+    let register_bank = rd.bytes(reg_len)?.to_vec();
+
+    let display_width = rd.u16()? as usize;
+    let display_height = rd.u16()? as usize;
+    // Checked before allocating anything sized by the file
+    if ![LORES, HIRES].contains(&(display_width, display_height)) {
+        return Err("invalid display size in save state".into());
+    }
+    let planes = rd.u8()?;
+    if planes != STATE_PLANES {
+        return Err(format!("unsupported plane count {} in save state", planes).into());
+    }
+    let display_len = display_width * display_height;
+    let mut display = vec![0; display_len];
+    for plane in 0..planes {
+        let packed = rd.bytes(display_len.div_ceil(8))?;
+        for (i, px) in display.iter_mut().enumerate() {
+            if packed[i / 8] & (0x80 >> (i % 8)) > 0 {
+                *px |= 1 << plane;
+            }
+        }
+    }
+    let plane_mask = rd.u8()?;
+
+    let rpl_len = rd.u8()? as usize;
+    let rpl_flags = rd.bytes(rpl_len)?.to_vec();
+
+    let audio_len = rd.u8()? as usize;
+    let audio_pattern = rd.bytes(audio_len)?.to_vec();
+    let audio_loaded = rd.u8()? != 0;
+    let pitch = rd.u8()?;
+
+    let prev_keys = rd.u16()?;
+    let rng = rd.u64()?;
+
+    if rd.pos != buf.len() {
+        return Err("trailing data after save state".into());
+    }
+
+    Ok(EmulatorState {
+        ram,
+        pc,
+        ireg,
+        stack,
+        stack_len,
+        delay_timer,
+        sound_timer,
+        register_bank,
+        display,
+        display_width,
+        display_height,
+        plane_mask,
+        rpl_flags,
+        audio_pattern,
+        audio_loaded,
+        pitch,
+        prev_keys,
+        rng,
+    })
+}
+
+struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.pos + n > self.buf.len() {
+            return Err("truncated save state".into());
+        }
+        let out = &self.buf[self.pos..(self.pos + n)];
+        self.pos += n;
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
 }
 
 #[cfg(test)]
@@ -37,4 +246,36 @@ mod test {
         assert_eq!(prog[prog.len() - 2], 0x13);
         assert_eq!(prog[prog.len() - 1], 0xDC);
     }
+
+    #[test]
+    fn test_state_roundtrip() {
+        use crate::emulator::Emulator;
+
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&read_program("programs/IBM_Logo.ch8").unwrap())
+            .unwrap();
+        for _ in 0..20 {
+            ch8.step(0).unwrap();
+        }
+
+        let buf = encode_state(ch8.get_state());
+        let state = decode_state(&buf).unwrap();
+        assert_eq!(&state, ch8.get_state());
+
+        assert!(decode_state(&buf[..buf.len() - 1]).is_err());
+
+        // Display width, height and plane count follow the registers
+        let display =
+            4 + 2 + 4 + state.ram.len() + 2 + 2 + 2 + 2 * state.stack.len() + 2 + 2 + 1 + 16;
+        let corrupt = |offset: usize, bytes: &[u8]| {
+            let mut buf = buf.clone();
+            buf[display + offset..display + offset + bytes.len()].copy_from_slice(bytes);
+            decode_state(&buf)
+        };
+        assert!(corrupt(0, &[64, 0, 32, 0, 2]).is_ok());
+        assert!(corrupt(0, &[0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+        assert!(corrupt(4, &[9]).is_err());
+        assert!(corrupt(0, &[128, 0, 64, 0]).is_err());
+        assert!(decode_state(b"CH8X").is_err());
+    }
 }
@@ -0,0 +1,139 @@
+use crate::debugger::Debugger;
+use crate::emulator::{EmulatorError, EmulatorState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    SaveState(u8),
+    LoadState(u8),
+    TogglePause,
+    Step,
+    StepOver,
+    StepOut,
+    RunTo(u16),
+    ToggleBreakpoint(u16),
+    /// Go back one frame, sent every frame while the rewind key is held
+    Rewind,
+    /// Restart the program from power on
+    Reset,
+    /// Double or halve the instructions per second
+    SpeedUp,
+    SpeedDown,
+}
+
+/// Everything the main loop needs from a user interface. `TUI` is the
+/// terminal implementation, other renderers can embed the core through it.
+pub trait Frontend {
+    /// Redraw from the emulator state, called once per frame
+    fn update(&mut self, state: &EmulatorState);
+
+    /// Sample the input devices, called once per frame before `update`
+    fn poll_input(&mut self);
+
+    /// Currently held keys, bit N set when hex key N is down
+    fn keys(&self) -> u16;
+
+    /// Commands such as save state hotkeys issued since the last call
+    fn take_commands(&mut self) -> Vec<Command>;
+
+    fn show_message(&mut self, msg: &str);
+
+    fn show_error(&mut self, err: &EmulatorError);
+
+    /// Measured frames and instructions per second
+    fn show_stats(&mut self, _fps: f64, _ips: f64) {}
+
+    /// Pause state and breakpoints, called once per frame after `update`
+    fn show_debugger(&mut self, _debugger: &Debugger) {}
+
+    /// The main loop exits once this returns false
+    fn is_running(&self) -> bool;
+}
+
+/// Frontend that keeps everything in memory, for tests and embedding
+#[derive(Default)]
+pub struct MemoryFrontend {
+    pub keys: u16,
+    pub commands: Vec<Command>,
+    pub display: Vec<u8>,
+    pub messages: Vec<String>,
+    pub error: Option<EmulatorError>,
+    pub frames: u64,
+    /// Stop running after this many frames
+    pub max_frames: Option<u64>,
+}
+
+impl MemoryFrontend {
+    pub fn new(max_frames: Option<u64>) -> Self {
+        Self {
+            max_frames,
+            ..Default::default()
+        }
+    }
+}
+
+impl Frontend for MemoryFrontend {
+    fn update(&mut self, state: &EmulatorState) {
+        self.display.clone_from(&state.display);
+    }
+
+    fn poll_input(&mut self) {
+        self.frames += 1;
+    }
+
+    fn keys(&self) -> u16 {
+        self.keys
+    }
+
+    fn take_commands(&mut self) -> Vec<Command> {
+        std::mem::take(&mut self.commands)
+    }
+
+    fn show_message(&mut self, msg: &str) {
+        self.messages.push(msg.to_string());
+    }
+
+    fn show_error(&mut self, err: &EmulatorError) {
+        self.error = Some(err.clone());
+    }
+
+    fn is_running(&self) -> bool {
+        self.max_frames.is_none_or(|max| self.frames < max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::NullSink;
+    use crate::config::Config;
+    use crate::file_io::write_program;
+    use crate::run_frontend;
+    use clap::Parser;
+
+    #[test]
+    fn test_memory_frontend() {
+        let cfg = Config::parse_from(["chip-8", "--program", "programs/IBM_Logo.ch8"]);
+        let mut frontend = MemoryFrontend::new(Some(10));
+        run_frontend(&cfg, &mut frontend, &mut NullSink).unwrap();
+
+        assert_eq!(frontend.frames, 10);
+        assert!(frontend.error.is_none());
+        assert!(frontend.display.iter().any(|px| *px != 0));
+    }
+
+    #[test]
+    fn test_memory_frontend_error() {
+        let rom = std::env::temp_dir().join(format!("underflow-{}.ch8", std::process::id()));
+        let rom = rom.to_str().unwrap();
+        write_program(rom, &[0x00, 0xEE]).unwrap();
+
+        let cfg = Config::parse_from(["chip-8", "--program", rom]);
+        let mut frontend = MemoryFrontend::new(Some(10));
+        let result = run_frontend(&cfg, &mut frontend, &mut NullSink);
+        std::fs::remove_file(rom).unwrap();
+
+        let err = EmulatorError::StackUnderflow { pc: 0x200 };
+        assert_eq!(frontend.error, Some(err.clone()));
+        assert_eq!(result.unwrap_err().to_string(), err.to_string());
+    }
+}
@@ -0,0 +1,331 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::BufWriter;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::audio::{AudioBackend, AudioSink};
+use crate::config::Config;
+use crate::emulator::{Emulator, EmulatorError, EmulatorState, StepOutcome};
+use crate::movie::Movie;
+use crate::new_emulator;
+use crate::timing::CycleBudget;
+use crate::trace::Tracer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DumpFormat {
+    Text,
+    Json,
+}
+
+/// Key mask to apply from a given frame onwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub keys: u16,
+}
+
+/// Run a program without a terminal, then print the final state to stdout.
+/// Emulator errors are reported after the dump so CI still gets the state.
+pub fn run(cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let replay = cfg.replay.as_deref().map(Movie::load).transpose()?;
+    let (mut ch8, _) = new_emulator(cfg, replay.as_ref())?;
+
+    let inputs = match (&replay, &cfg.input) {
+        (Some(movie), _) => movie.input_events(),
+        (None, Some(fname)) => parse_input(&fs::read_to_string(fname)?)?,
+        (None, None) => Vec::new(),
+    };
+    let frames = replay
+        .as_ref()
+        .map_or(cfg.frames, |m| m.frames.len() as u64);
+    let ips = replay
+        .as_ref()
+        .map_or(cfg.instructions_per_second(), |m| m.ips);
+
+    let mut tracer = match &cfg.trace {
+        Some(fname) => Some(Tracer::create(fname, cfg.trace_filter())?),
+        None => None,
+    };
+
+    // Only a recording makes sense without a terminal
+    let mut audio = match cfg.audio {
+        AudioBackend::Wav => Some(cfg.audio.open(cfg.audio_file.as_deref())?),
+        _ => None,
+    };
+
+    let mut budget = CycleBudget::new(ips);
+    let (result, frames, cycles) = run_frames(
+        &mut ch8,
+        &inputs,
+        &mut budget,
+        frames,
+        cfg.cycles,
+        tracer.as_mut(),
+        audio.as_mut().map(|a| a.as_mut() as &mut dyn AudioSink),
+    );
+    // Finish both before reporting either error
+    let traced = tracer.map(|tracer| tracer.finish()).transpose();
+    let recorded = match audio.as_mut() {
+        Some(audio) => audio.finish(),
+        None => Ok(()),
+    };
+    traced?;
+    recorded?;
+
+    let out = match cfg.format {
+        DumpFormat::Text => dump_text(ch8.get_state(), frames, cycles),
+        DumpFormat::Json => dump_json(ch8.get_state(), frames, cycles, result.as_ref().err())?,
+    };
+    print!("{}", out);
+
+    Ok(result?)
+}
+
+/// Run until `max_frames` frames or `max_cycles` instructions have executed,
+/// whichever comes first. Returns the frames and cycles actually run.
+pub fn run_frames(
+    ch8: &mut Emulator,
+    inputs: &[InputEvent],
+    budget: &mut CycleBudget,
+    max_frames: u64,
+    max_cycles: Option<u64>,
+    mut tracer: Option<&mut Tracer<BufWriter<File>>>,
+    mut audio: Option<&mut dyn AudioSink>,
+) -> (Result<(), EmulatorError>, u64, u64) {
+    let mut keys = 0;
+    let mut next_input = 0;
+    let mut cycles = 0;
+
+    for frame in 0..max_frames {
+        while next_input < inputs.len() && inputs[next_input].frame <= frame {
+            keys = inputs[next_input].keys;
+            next_input += 1;
+        }
+
+        for _ in 0..budget.next_frame() {
+            if max_cycles.is_some_and(|max| cycles >= max) {
+                return (Ok(()), frame, cycles);
+            }
+            if let Some(tracer) = tracer.as_mut() {
+                tracer.before_step(ch8);
+            }
+            let outcome = ch8.step(keys);
+            if let (Some(tracer), Ok(outcome)) = (tracer.as_mut(), &outcome) {
+                tracer.after_step(ch8, *outcome);
+            }
+            match outcome {
+                Ok(StepOutcome::Exited) => return (Ok(()), frame, cycles),
+                Ok(StepOutcome::Executed) => cycles += 1,
+                Ok(StepOutcome::Waiting | StepOutcome::Idle) => (),
+                Err(err) => return (Err(err), frame, cycles),
+            }
+        }
+        if let Some(audio) = audio.as_mut() {
+            audio.frame(ch8.get_state());
+        }
+        ch8.tick();
+    }
+
+    (Ok(()), max_frames, cycles)
+}
+
+/// Parse an input script, one `<frame> <keys...>` entry per line where keys
+/// are the hex digits held from that frame on, or `-` for none. Blank lines
+/// and lines starting with `#` are ignored.
+pub fn parse_input(script: &str) -> Result<Vec<InputEvent>, Box<dyn Error>> {
+    let mut events: Vec<InputEvent> = Vec::new();
+
+    for (lineno, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let frame: u64 = fields
+            .next()
+            .unwrap()
+            .parse()
+            .map_err(|_| format!("line {}: invalid frame number", lineno + 1))?;
+        if events.last().is_some_and(|e| e.frame > frame) {
+            return Err(format!("line {}: frames must be in order", lineno + 1).into());
+        }
+
+        let mut keys = 0;
+        for field in fields {
+            if field == "-" {
+                continue;
+            }
+            let key = u8::from_str_radix(field, 16)
+                .ok()
+                .filter(|k| *k < 16)
+                .ok_or_else(|| format!("line {}: invalid key '{}'", lineno + 1, field))?;
+            keys |= 1 << key;
+        }
+
+        events.push(InputEvent { frame, keys });
+    }
+
+    Ok(events)
+}
+
+fn display_rows(state: &EmulatorState) -> Vec<String> {
+    state
+        .display
+        .chunks(state.display_width)
+        .map(|row| {
+            row.iter()
+                .map(|px| match px & 0x03 {
+                    0 => '.',
+                    1 => '#',
+                    2 => 'o',
+                    _ => '@',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn dump_text(state: &EmulatorState, frames: u64, cycles: u64) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "frames: {}  cycles: {}", frames, cycles).unwrap();
+    writeln!(
+        out,
+        "PC: {:04X}  I: {:04X}  DT: {:02X}  ST: {:02X}",
+        state.pc, state.ireg, state.delay_timer, state.sound_timer
+    )
+    .unwrap();
+    for (i, regs) in state.register_bank.chunks(8).enumerate() {
+        write!(out, "V{:X}-V{:X}:", i * 8, i * 8 + 7).unwrap();
+        for val in regs {
+            write!(out, " {:02X}", val).unwrap();
+        }
+        out.push('\n');
+    }
+    write!(out, "stack:").unwrap();
+    for val in state.stack.iter().take(state.stack_len) {
+        write!(out, " {:04X}", val).unwrap();
+    }
+    out.push('\n');
+
+    for row in display_rows(state) {
+        writeln!(out, "{}", row).unwrap();
+    }
+
+    out
+}
+
+/// Final state for `--format json`, the display as one string per row
+#[derive(Serialize)]
+struct JsonDump<'a> {
+    frames: u64,
+    cycles: u64,
+    pc: u16,
+    i: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    registers: &'a [u8],
+    stack: &'a [u16],
+    display_width: usize,
+    display_height: usize,
+    display: Vec<String>,
+    error: Option<String>,
+}
+
+pub fn dump_json(
+    state: &EmulatorState,
+    frames: u64,
+    cycles: u64,
+    err: Option<&EmulatorError>,
+) -> Result<String, Box<dyn Error>> {
+    let dump = JsonDump {
+        frames,
+        cycles,
+        pc: state.pc,
+        i: state.ireg,
+        delay_timer: state.delay_timer,
+        sound_timer: state.sound_timer,
+        registers: &state.register_bank,
+        stack: &state.stack[..state.stack_len],
+        display_width: state.display_width,
+        display_height: state.display_height,
+        display: display_rows(state),
+        error: err.map(|err| err.to_string()),
+    };
+    Ok(serde_json::to_string_pretty(&dump)? + "\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_io::read_program;
+
+    #[test]
+    fn test_parse_input() {
+        let events = parse_input("# comment\n0 -\n10 1 a\n\n20 F\n").unwrap();
+        assert_eq!(
+            events,
+            vec![
+                InputEvent { frame: 0, keys: 0 },
+                InputEvent {
+                    frame: 10,
+                    keys: 0x0402
+                },
+                InputEvent {
+                    frame: 20,
+                    keys: 0x8000
+                },
+            ]
+        );
+
+        assert!(parse_input("10 G").is_err());
+        assert!(parse_input("10 1\n5 2").is_err());
+    }
+
+    #[test]
+    fn test_run_ibm_logo() {
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&read_program("programs/IBM_Logo.ch8").unwrap())
+            .unwrap();
+
+        let mut budget = CycleBudget::new(600.);
+        let (result, frames, _) = run_frames(&mut ch8, &[], &mut budget, 60, None, None, None);
+        assert!(result.is_ok());
+        assert_eq!(frames, 60);
+
+        let dump = dump_text(ch8.get_state(), frames, 0);
+        assert!(dump.contains("########"));
+    }
+
+    #[test]
+    fn test_dump_json() {
+        let ch8 = Emulator::default();
+        let err = EmulatorError::InvalidState {
+            reason: "a \"quoted\" \\ reason",
+        };
+        let dump = dump_json(ch8.get_state(), 1, 2, Some(&err)).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&dump).unwrap();
+        assert_eq!(json["cycles"], 2);
+        assert_eq!(json["registers"].as_array().unwrap().len(), 16);
+        assert_eq!(json["display"].as_array().unwrap().len(), 32);
+        assert_eq!(json["error"], err.to_string());
+    }
+
+    #[test]
+    fn test_run_waiting() {
+        // LD V0, 1 then LD V1, K with no key held
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(&[0x60, 0x01, 0xF1, 0x0A]).unwrap();
+
+        let mut budget = CycleBudget::new(600.);
+        let (result, frames, cycles) =
+            run_frames(&mut ch8, &[], &mut budget, 10, Some(5), None, None);
+        assert!(result.is_ok());
+        assert_eq!(frames, 10);
+        assert_eq!(cycles, 1);
+    }
+}
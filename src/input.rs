@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::io::{stdout, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use serde::{Deserialize, Serialize};
+use termion::event::Key;
+use termion::input::{Keys, TermRead};
+use termion::AsyncReader;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputBackend {
+    /// Global keyboard state through X11, sees real key presses and releases
+    #[default]
+    Device,
+    /// Key events from the terminal, released after a hold timeout
+    Terminal,
+    /// Press and release events through the kitty keyboard protocol, like
+    /// `terminal` when the terminal doesn't support it
+    Kitty,
+}
+
+/// Where the TUI reads the keyboard from
+pub trait KeySource {
+    /// Keys held down right now
+    fn held(&mut self) -> Vec<Keycode>;
+}
+
+impl InputBackend {
+    pub fn open(self, hold: Duration) -> Box<dyn KeySource> {
+        match self {
+            InputBackend::Device => Box::new(DeviceKeys(DeviceState::new())),
+            InputBackend::Terminal => Box::new(TerminalKeys {
+                events: termion::async_stdin().keys(),
+                timer: HoldTimer::new(hold),
+            }),
+            InputBackend::Kitty => {
+                let mut stdin = termion::async_stdin();
+                match query_kitty(&mut stdin) {
+                    true => Box::new(KittyKeys::new(stdin)),
+                    false => Box::new(TerminalKeys {
+                        events: stdin.keys(),
+                        timer: HoldTimer::new(hold),
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Whether the terminal also echoes the keys, leaving them buffered on
+    /// stdin when the program exits
+    pub fn leaves_input(self) -> bool {
+        self == InputBackend::Device
+    }
+}
+
+struct DeviceKeys(DeviceState);
+
+impl KeySource for DeviceKeys {
+    fn held(&mut self) -> Vec<Keycode> {
+        self.0.get_keys()
+    }
+}
+
+const MODIFIERS: [Keycode; 6] = [
+    Keycode::LShift,
+    Keycode::RShift,
+    Keycode::LControl,
+    Keycode::RControl,
+    Keycode::LAlt,
+    Keycode::RAlt,
+];
+
+/// Terminals only send key presses, auto repeat included, so a key counts
+/// as held until no event for it has arrived within the timeout
+struct HoldTimer {
+    hold: Duration,
+    seen: BTreeMap<String, (Keycode, Instant)>,
+}
+
+impl HoldTimer {
+    fn new(hold: Duration) -> Self {
+        Self {
+            hold,
+            seen: BTreeMap::new(),
+        }
+    }
+
+    /// Record one key event. Modifiers only come along with other keys, so
+    /// the ones missing from this event are released straight away.
+    fn press(&mut self, keys: &[Keycode], now: Instant) {
+        for modifier in MODIFIERS.iter().filter(|m| !keys.contains(m)) {
+            self.seen.remove(&modifier.to_string());
+        }
+        for key in keys {
+            self.seen.insert(key.to_string(), (*key, now));
+        }
+    }
+
+    fn held(&mut self, now: Instant) -> Vec<Keycode> {
+        let hold = self.hold;
+        self.seen
+            .retain(|_, (_, at)| now.duration_since(*at) < hold);
+        self.seen.values().map(|(key, _)| *key).collect()
+    }
+}
+
+struct TerminalKeys {
+    events: Keys<AsyncReader>,
+    timer: HoldTimer,
+}
+
+impl KeySource for TerminalKeys {
+    fn held(&mut self) -> Vec<Keycode> {
+        let now = Instant::now();
+        for key in self.events.by_ref().flatten() {
+            self.timer.press(&keycodes(key), now);
+        }
+        self.timer.held(now)
+    }
+}
+
+fn char_keycode(c: char) -> Option<Keycode> {
+    match c {
+        '0'..='9' => format!("Key{}", c).parse().ok(),
+        'a'..='z' | 'A'..='Z' => c.to_ascii_uppercase().to_string().parse().ok(),
+        ' ' => Some(Keycode::Space),
+        '\n' | '\r' => Some(Keycode::Enter),
+        '\t' => Some(Keycode::Tab),
+        _ => None,
+    }
+}
+
+/// The keys a terminal key event stands for, modifiers included
+fn keycodes(key: Key) -> Vec<Keycode> {
+    let with = |modifier: Keycode, key: Option<Keycode>| match key {
+        Some(key) => vec![modifier, key],
+        None => Vec::new(),
+    };
+    match key {
+        Key::Char(c) if c.is_ascii_uppercase() => with(Keycode::LShift, char_keycode(c)),
+        Key::Char(c) => char_keycode(c).into_iter().collect(),
+        Key::Ctrl(c) => with(Keycode::LControl, char_keycode(c)),
+        Key::Alt(c) => with(Keycode::LAlt, char_keycode(c)),
+        Key::F(n) => format!("F{}", n).parse().into_iter().collect(),
+        Key::Backspace => vec![Keycode::Backspace],
+        Key::Left => vec![Keycode::Left],
+        Key::Right => vec![Keycode::Right],
+        Key::Up => vec![Keycode::Up],
+        Key::Down => vec![Keycode::Down],
+        Key::Esc => vec![Keycode::Escape],
+        _ => Vec::new(),
+    }
+}
+
+/// Report press, repeat and release events, and every key as an escape code
+const KITTY_FLAGS: u8 = 1 | 2 | 8;
+
+struct KittyKeys {
+    stdin: AsyncReader,
+    parser: KittyParser,
+}
+
+impl KittyKeys {
+    fn new(stdin: AsyncReader) -> Self {
+        let mut out = stdout();
+        write!(out, "\x1b[>{}u", KITTY_FLAGS).unwrap();
+        out.flush().unwrap();
+        Self {
+            stdin,
+            parser: KittyParser::default(),
+        }
+    }
+}
+
+/// How long to wait for the terminal to answer the support query
+const KITTY_QUERY_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Ask for the current kitty keyboard flags, then for the primary device
+/// attributes which every terminal answers. Only terminals with the
+/// protocol reply to the first query, and they do so before the second.
+fn query_kitty(stdin: &mut AsyncReader) -> bool {
+    let mut out = stdout();
+    write!(out, "\x1b[?u\x1b[c").unwrap();
+    out.flush().unwrap();
+
+    let start = Instant::now();
+    let mut reply = Vec::new();
+    let mut buf = [0; 64];
+    while start.elapsed() < KITTY_QUERY_TIMEOUT {
+        match stdin.read(&mut buf) {
+            Ok(n @ 1..) => reply.extend_from_slice(&buf[..n]),
+            _ => thread::sleep(Duration::from_millis(5)),
+        }
+        if let Some(supported) = kitty_reply(&reply) {
+            return supported;
+        }
+    }
+    false
+}
+
+/// Whether the replies so far show kitty protocol support, `None` until
+/// either reply has arrived
+fn kitty_reply(reply: &[u8]) -> Option<bool> {
+    let mut rest = reply;
+    while let Some(start) = rest.windows(3).position(|w| w == b"\x1b[?") {
+        let params = &rest[start + 3..];
+        let end = params
+            .iter()
+            .position(|b| !(b.is_ascii_digit() || *b == b';'))?;
+        match params[end] {
+            b'u' => return Some(true),
+            b'c' => return Some(false),
+            _ => rest = &params[end..],
+        }
+    }
+    None
+}
+
+impl KeySource for KittyKeys {
+    fn held(&mut self) -> Vec<Keycode> {
+        let mut buf = [0; 64];
+        while let Ok(n @ 1..) = self.stdin.read(&mut buf) {
+            buf[..n].iter().for_each(|b| self.parser.push(*b));
+        }
+        self.parser.held()
+    }
+}
+
+impl Drop for KittyKeys {
+    fn drop(&mut self) {
+        // Restore the flags the terminal had before
+        let mut out = stdout();
+        write!(out, "\x1b[<u").unwrap();
+        out.flush().unwrap();
+    }
+}
+
+/// Tracks held keys from kitty keyboard protocol escape sequences, of the
+/// form `CSI code[:alternates] ; modifiers[:event] terminator`
+#[derive(Default)]
+struct KittyParser {
+    buf: Vec<u8>,
+    down: BTreeMap<String, Keycode>,
+}
+
+impl KittyParser {
+    fn push(&mut self, byte: u8) {
+        if self.buf.is_empty() && byte != 0x1b {
+            return;
+        }
+        self.buf.push(byte);
+        if self.buf.len() == 2 && byte != b'[' {
+            self.buf.clear();
+        } else if self.buf.len() > 2 && (0x40..=0x7E).contains(&byte) {
+            let seq = std::mem::take(&mut self.buf);
+            self.sequence(&seq[2..seq.len() - 1], byte);
+        } else if self.buf.len() > 32 {
+            self.buf.clear();
+        }
+    }
+
+    fn sequence(&mut self, params: &[u8], terminator: u8) {
+        let params = String::from_utf8_lossy(params);
+        let mut fields = params.split(';');
+        let code: u32 = match fields.next().and_then(|f| f.split(':').next()) {
+            Some("") => 1,
+            Some(code) => match code.parse() {
+                Ok(code) => code,
+                Err(_) => return,
+            },
+            None => return,
+        };
+        let event = fields
+            .next()
+            .and_then(|f| f.split(':').nth(1))
+            .and_then(|e| e.parse().ok())
+            .unwrap_or(1);
+
+        let key = match (code, terminator) {
+            (_, b'u') => match code {
+                8 | 127 => Some(Keycode::Backspace),
+                9 => Some(Keycode::Tab),
+                13 => Some(Keycode::Enter),
+                27 => Some(Keycode::Escape),
+                57441 => Some(Keycode::LShift),
+                57442 => Some(Keycode::LControl),
+                57443 => Some(Keycode::LAlt),
+                57447 => Some(Keycode::RShift),
+                57448 => Some(Keycode::RControl),
+                57449 => Some(Keycode::RAlt),
+                _ => char::from_u32(code).and_then(char_keycode),
+            },
+            (1, b'A') => Some(Keycode::Up),
+            (1, b'B') => Some(Keycode::Down),
+            (1, b'C') => Some(Keycode::Right),
+            (1, b'D') => Some(Keycode::Left),
+            (1, b'P') => Some(Keycode::F1),
+            (1, b'Q') => Some(Keycode::F2),
+            (1, b'S') => Some(Keycode::F4),
+            (_, b'~') => match code {
+                11 => Some(Keycode::F1),
+                12 => Some(Keycode::F2),
+                13 => Some(Keycode::F3),
+                14 => Some(Keycode::F4),
+                15 => Some(Keycode::F5),
+                17 => Some(Keycode::F6),
+                18 => Some(Keycode::F7),
+                19 => Some(Keycode::F8),
+                20 => Some(Keycode::F9),
+                21 => Some(Keycode::F10),
+                23 => Some(Keycode::F11),
+                24 => Some(Keycode::F12),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let Some(key) = key {
+            match event {
+                3 => self.down.remove(&key.to_string()),
+                _ => self.down.insert(key.to_string(), key),
+            };
+        }
+    }
+
+    fn held(&self) -> Vec<Keycode> {
+        self.down.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_terminal_keys() {
+        assert_eq!(keycodes(Key::Char('q')), vec![Keycode::Q]);
+        assert_eq!(keycodes(Key::Char('Q')), vec![Keycode::LShift, Keycode::Q]);
+        assert_eq!(
+            keycodes(Key::Ctrl('q')),
+            vec![Keycode::LControl, Keycode::Q]
+        );
+        assert_eq!(keycodes(Key::Alt('3')), vec![Keycode::LAlt, Keycode::Key3]);
+        assert_eq!(keycodes(Key::F(11)), vec![Keycode::F11]);
+
+        let start = Instant::now();
+        let mut timer = HoldTimer::new(Duration::from_millis(100));
+        timer.press(&[Keycode::LControl, Keycode::Key1], start);
+        timer.press(&[Keycode::W], start + Duration::from_millis(50));
+        assert_eq!(
+            timer.held(start + Duration::from_millis(60)),
+            vec![Keycode::Key1, Keycode::W]
+        );
+        assert_eq!(
+            timer.held(start + Duration::from_millis(120)),
+            vec![Keycode::W]
+        );
+        assert!(timer.held(start + Duration::from_millis(200)).is_empty());
+    }
+
+    #[test]
+    fn test_kitty_reply() {
+        assert_eq!(kitty_reply(b""), None);
+        assert_eq!(kitty_reply(b"\x1b[?0u\x1b[?62;22c"), Some(true));
+        assert_eq!(kitty_reply(b"\x1b[?62;22c"), Some(false));
+        assert_eq!(kitty_reply(b"q\x1b[?1;2"), None);
+    }
+
+    #[test]
+    fn test_kitty_parser() {
+        let mut parser = KittyParser::default();
+        let feed = |parser: &mut KittyParser, s: &str| s.bytes().for_each(|b| parser.push(b));
+
+        // w pressed, left ctrl pressed, F5 pressed then released, up repeated
+        feed(
+            &mut parser,
+            "\x1b[119u\x1b[57442;5u\x1b[15~\x1b[15;1:3~\x1b[1;1:2A",
+        );
+        assert_eq!(
+            parser.held(),
+            vec![Keycode::LControl, Keycode::Up, Keycode::W]
+        );
+
+        feed(
+            &mut parser,
+            "\x1b[119;1:3u\x1b[57442;5:3ugarbage\x1b[1;1:3A",
+        );
+        assert!(parser.held().is_empty());
+    }
+}
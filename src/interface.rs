@@ -1,35 +1,106 @@
+use std::collections::BTreeSet;
 use std::io::{stdin, stdout, Stdout, Write};
+use std::time::Duration;
 
+use termion::color;
 use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
 use termion::style;
 
-use device_query::{DeviceQuery, DeviceState, Keycode};
+use device_query::Keycode;
+
+use crate::debugger::Debugger;
+use crate::emulator::{EmulatorError, EmulatorState};
+use crate::frontend::{Command, Frontend};
+use crate::input::{InputBackend, KeySource};
+use crate::keymap::{Action, Keymap};
+use crate::render::{Phosphor, RenderMode};
+use crate::theme::Theme;
+
+/// Appearance and input settings for the TUI
+pub struct TuiOptions {
+    pub render: RenderMode,
+    /// Frames unlit pixels take to fade out
+    pub persistence: u8,
+    pub theme: Theme,
+    pub keyboard: InputBackend,
+    /// How long terminal key presses count as held
+    pub key_hold: Duration,
+    pub keymap: Keymap,
+    /// Shown at the top of the display pane
+    pub title: Option<String>,
+}
 
-use crate::emulator::EmulatorState;
+impl Default for TuiOptions {
+    fn default() -> Self {
+        Self {
+            render: RenderMode::default(),
+            persistence: 0,
+            theme: Theme::default(),
+            keyboard: InputBackend::default(),
+            key_hold: Duration::from_millis(200),
+            keymap: Keymap::default(),
+            title: None,
+        }
+    }
+}
 
 pub struct TUI {
     stdout: RawTerminal<Stdout>,
-    display: Vec<bool>,
+    input: Box<dyn KeySource>,
+    keyboard: InputBackend,
+    keymap: Keymap,
+    title: Option<String>,
+    render: RenderMode,
+    phosphor: Phosphor,
+    theme: Theme,
+    /// Size of the display pane in terminal cells
+    width: u16,
+    height: u16,
+    /// Display resolution in pixels
+    res: (usize, usize),
+    display: Vec<u8>,
     prog: Vec<u8>,
     pc: Option<u16>,
     prog_offset: Option<u16>,
     keys: Option<u16>,
+    prev_pressed: Vec<Keycode>,
+    commands: Vec<Command>,
     running: bool,
+    breakpoints: BTreeSet<u16>,
+    cursor: Option<u16>,
+    /// Registers as of the last frame, to highlight changes
+    registers: Vec<u8>,
 }
 
 impl TUI {
-    pub fn new() -> Self {
+    pub fn new(opts: TuiOptions) -> Self {
         let stdout = stdout().into_raw_mode().unwrap();
+        let (width, height) = opts.render.cells(64, 32);
 
         Self {
             stdout,
-            display: vec![false; 32 * 64],
-            prog: vec![0; 4096],
+            input: opts.keyboard.open(opts.key_hold),
+            keyboard: opts.keyboard,
+            keymap: opts.keymap,
+            title: opts.title,
+            render: opts.render,
+            phosphor: Phosphor::new(opts.persistence),
+            theme: opts.theme,
+            width: width as u16,
+            height: height as u16,
+            res: (64, 32),
+            display: vec![0; 32 * 64],
+            prog: vec![0; 0x10000],
             pc: None,
             prog_offset: None,
             keys: None,
+            prev_pressed: Vec::new(),
+            commands: Vec::new(),
             running: true,
+            breakpoints: BTreeSet::new(),
+            cursor: None,
+            registers: Vec::new(),
         }
     }
 
@@ -42,11 +113,34 @@ impl TUI {
             termion::cursor::Hide
         )
         .unwrap();
-        write!(self.stdout, "┌────────────────────────────Display─────────────────────────────┬──────────────────────────RAM───────────────────────────┐\r\n").unwrap();
-        for i in 0..32 {
+        // The display pane is sized by the resolution and render mode,
+        // everything to its right shifts along with it
+        let w = self.width as usize;
+        let dashes = |n: usize| "─".repeat(n);
+        let blank = " ".repeat(w - 7);
+
+        let label: String = match &self.title {
+            Some(title) => format!(" {} ", title).chars().take(w).collect(),
+            None => "Display".to_string(),
+        };
+        let n = label.chars().count();
+        write!(
+            self.stdout,
+            "┌{}{}{}┬{}RAM{}┐\r\n",
+            dashes((w - n) / 2),
+            label,
+            dashes(w - n - (w - n) / 2),
+            dashes(26),
+            dashes(27)
+        )
+        .unwrap();
+        for i in 0..self.height {
             write!(
                 self.stdout,
-                "│                                                                │"
+                "│{}{}{}│",
+                self.theme.background.bg(),
+                " ".repeat(w),
+                color::Bg(color::Reset)
             )
             .unwrap();
             write!(
@@ -56,91 +150,84 @@ impl TUI {
             )
             .unwrap();
         }
-        write!(self.stdout, "├────┬─────────────────────────────────────────────────────────┬─┴──────────┬──────────Reg─────────┬─────────Stack────────┤\r\n").unwrap();
-        write!(self.stdout, "│123C│                                                         │   PC: .... │....  ....  ....  ....│....  ....  ....  ....│\r\n").unwrap();
-        write!(self.stdout, "│456D│                                                         │ IREG: .... │....  ....  ....  ....│....  ....  ....  ....│\r\n").unwrap();
-        write!(self.stdout, "│789E│                                                         │Delay:   .. │....  ....  ....  ....│....  ....  ....  ....│\r\n").unwrap();
-        write!(self.stdout, "│A0BF│                                                         │Sound:   .. │....  ....  ....  ....│....  ....  ....  ....│\r\n").unwrap();
-        write!(self.stdout, "└────┘                                                         └────────────┴──────────────────────┴──────────────────────┘\r\n").unwrap();
+        write!(
+            self.stdout,
+            "├────┬{}┬─┴──────────┬──────────Reg─────────┬─────────Stack────────┤\r\n",
+            dashes(w - 7)
+        )
+        .unwrap();
+        write!(
+            self.stdout,
+            "│123C│{}│   PC: .... │....  ....  ....  ....│....  ....  ....  ....│\r\n",
+            blank
+        )
+        .unwrap();
+        write!(
+            self.stdout,
+            "│456D│{}│ IREG: .... │....  ....  ....  ....│....  ....  ....  ....│\r\n",
+            blank
+        )
+        .unwrap();
+        write!(
+            self.stdout,
+            "│789E│{}│Delay:   .. │....  ....  ....  ....│....  ....  ....  ....│\r\n",
+            blank
+        )
+        .unwrap();
+        write!(
+            self.stdout,
+            "│A0BF│{}│Sound:   .. │....  ....  ....  ....│....  ....  ....  ....│\r\n",
+            blank
+        )
+        .unwrap();
+        write!(
+            self.stdout,
+            "└────┘{}└────────────┴──────────────────────┴──────────────────────┘\r\n",
+            blank
+        )
+        .unwrap();
 
         self.stdout.flush().unwrap();
     }
 
-    pub fn update_tui(&mut self, state: &EmulatorState) {
-        self.draw_display(&state.display);
-        self.draw_keypad();
-        self.draw_program(&state.ram, state.pc);
-        self.draw_values(state.pc, state.ireg, state.delay_timer, state.sound_timer);
-        self.draw_registers(&state.register_bank);
-        self.draw_stack(&state.stack, state.stack_len);
-
-        self.stdout.flush().unwrap();
+    fn resize(&mut self, width: usize, height: usize) {
+        let (cols, rows) = self.render.cells(width, height);
+        self.width = cols as u16;
+        self.height = rows as u16;
+        self.res = (width, height);
+        self.display = vec![0; width * height];
+        self.pc = None;
+        self.prog_offset = None;
+        self.init_tui();
     }
 
-    pub fn is_running(&self) -> bool {
-        self.running
+    fn message_len(&self) -> usize {
+        (self.width - 9) as usize
     }
 
-    pub fn update_keys(&mut self) {
-        let device_state = DeviceState::new();
-        let keys: Vec<Keycode> = device_state.get_keys();
-
-        if keys.contains(&Keycode::LControl) || keys.contains(&Keycode::RControl) {
-            if keys.contains(&Keycode::Q) {
-                self.running = false;
-            }
-        } else {
-            let keycodes: Vec<_> = vec![
-                Keycode::V,
-                Keycode::C,
-                Keycode::X,
-                Keycode::Z,
-                Keycode::F,
-                Keycode::D,
-                Keycode::S,
-                Keycode::A,
-                Keycode::R,
-                Keycode::E,
-                Keycode::W,
-                Keycode::Q,
-                Keycode::Key4,
-                Keycode::Key3,
-                Keycode::Key2,
-                Keycode::Key1,
-            ];
-
-            let mut k = 0;
-            for keycode in keycodes {
-                k *= 2;
-                if keys.contains(&keycode) {
-                    k += 1;
+    /// Redraw the cells holding any pixel that changed since the last frame
+    fn draw_display(&mut self, display: &[u8]) {
+        let width = self.res.0;
+        let (cw, ch) = self.render.cell_size();
+        for r in 0..self.height as usize {
+            for c in 0..self.width as usize {
+                let rows =
+                    (r * ch..(r + 1) * ch).map(|y| y * width + c * cw..y * width + (c + 1) * cw);
+                let mut changed = false;
+                for row in rows {
+                    if self.display[row.clone()] != display[row.clone()] {
+                        self.display[row.clone()].copy_from_slice(&display[row]);
+                        changed = true;
+                    }
                 }
-            }
-
-            self.keys = Some(k);
-        }
-    }
-
-    pub fn get_keys(&self) -> u16 {
-        self.keys.unwrap()
-    }
-
-    fn draw_display(&mut self, display: &[bool]) {
-        for r in 0..32 {
-            for c in 0..64 {
-                let idx = (r * 64 + c) as usize;
-                if self.display[idx] != display[idx] {
+                if changed {
                     write!(
                         self.stdout,
                         "{}{}",
-                        termion::cursor::Goto(c + 2, r + 2),
-                        match display[(r * 64 + c) as usize] {
-                            true => "█",
-                            false => " ",
-                        }
+                        termion::cursor::Goto(c as u16 + 2, r as u16 + 2),
+                        self.render.glyph(display, width, c, r, &self.theme)
                     )
                     .unwrap();
-                    self.display[idx] = display[idx];
                 }
             }
         }
@@ -151,17 +238,18 @@ impl TUI {
             '1', '2', '3', 'C', '4', '5', '6', 'D', '7', '8', '9', 'E', 'A', '0', 'B', 'F',
         ];
         for (i, sym) in syms.iter().enumerate() {
-            let r = 35 + ((i / 4) as u16);
+            let r = self.height + 3 + ((i / 4) as u16);
             let c = 2 + ((i % 4) as u16);
+            let bit = sym.to_digit(16).unwrap();
             if let Some(k) = self.keys {
-                if (k & (1 << i)) > 0 {
+                if (k & (1 << bit)) > 0 {
                     write!(
                         self.stdout,
                         "{}{}{}{}",
                         termion::cursor::Goto(c, r),
-                        style::Invert,
+                        self.theme.key.highlight(),
                         sym,
-                        style::NoInvert
+                        style::Reset
                     )
                     .unwrap();
                 } else {
@@ -172,140 +260,177 @@ impl TUI {
     }
 
     fn draw_program(&mut self, prog: &[u8], pc: u16) {
-        for i in 0..2048 {
+        let rows = self.height;
+        let lines = (self.prog.len() / 16) as u16;
+
+        let mut changed = Vec::new();
+        for i in 0..(self.prog.len() / 2) {
             if self.prog[2 * i] != prog[2 * i] || self.prog[2 * i + 1] != prog[2 * i + 1] {
                 self.prog[2 * i] = prog[2 * i];
                 self.prog[2 * i + 1] = prog[2 * i + 1];
-
-                if let Some(offset) = self.prog_offset {
-                    let line = ((2 * i) / 16) as u16;
-                    if offset <= line && line < (offset + 32) {
-                        // lines rendered
-                        let cmd = ((self.prog[2 * i] as u16) << 8) + (self.prog[2 * i + 1] as u16);
-                        write!(
-                            self.stdout,
-                            "{}{:04X}",
-                            termion::cursor::Goto(
-                                (((2 * i) % 16) * 3 + 75) as u16,
-                                line - self.prog_offset.unwrap() + 2
-                            ),
-                            cmd
-                        )
-                        .unwrap();
-                    }
-                }
+                changed.push((2 * i) as u16);
             }
         }
 
-        if let Some(offset) = self.prog_offset {
-            let line = pc / 16;
-
-            if line < offset || line >= (offset + 32) {
-                // PC out of disp range
-                if line < offset {
-                    self.prog_offset = Some(line);
-                } else {
-                    self.prog_offset = Some(offset + line - (offset + 31));
+        // Follow the PC when it moves, otherwise leave the view where the
+        // cursor put it
+        let old_pc = self.pc.replace(pc);
+        let line = pc / 16;
+        let offset = match self.prog_offset {
+            None => Some(line.min(lines - rows)),
+            Some(_) if old_pc == Some(pc) => None,
+            Some(offset) if line < offset => Some(line),
+            Some(offset) if line >= offset + rows => Some(line - (rows - 1)),
+            Some(_) => None,
+        };
+
+        match offset {
+            Some(offset) => {
+                self.prog_offset = Some(offset);
+                self.draw_rows();
+            }
+            None => {
+                for addr in changed {
+                    self.draw_word(addr);
                 }
-
-                for l in 0..32 {
-                    write!(
-                        self.stdout,
-                        "{}{:04X}",
-                        termion::cursor::Goto(68, l + 2),
-                        self.prog_offset.unwrap() + l
-                    )
-                    .unwrap();
-
-                    for c in 0..8 {
-                        let pc = (self.prog_offset.unwrap() + l) * 16 + 2 * c;
-                        let cmd = ((self.prog[pc as usize] as u16) << 8)
-                            + (self.prog[(pc + 1) as usize] as u16);
-
-                        write!(
-                            self.stdout,
-                            "{}{:04X}",
-                            termion::cursor::Goto(6 * c + 75, l + 2),
-                            cmd
-                        )
-                        .unwrap();
-                    }
+                if let Some(old_pc) = old_pc {
+                    self.draw_word(old_pc);
                 }
+                self.draw_word(pc);
             }
-        } else {
-            self.prog_offset = Some(pc / 16);
-            for l in 0..32 {
-                write!(
-                    self.stdout,
-                    "{}{:04X}",
-                    termion::cursor::Goto(68, l + 2),
-                    (self.prog_offset.unwrap() + l) * 16,
-                )
-                .unwrap();
+        }
+    }
 
-                for c in 0..8 {
-                    let pc = (self.prog_offset.unwrap() + l) * 16 + 2 * c;
-                    let cmd = ((self.prog[pc as usize] as u16) << 8)
-                        + (self.prog[(pc + 1) as usize] as u16);
+    /// Redraw every line of the RAM pane from `prog_offset`
+    fn draw_rows(&mut self) {
+        let offset = self.prog_offset.unwrap();
+        for l in 0..self.height {
+            write!(
+                self.stdout,
+                "{}{}{:04X}{}",
+                termion::cursor::Goto(self.width + 4, l + 2),
+                self.theme.ram.fg(),
+                (offset + l) * 16,
+                style::Reset
+            )
+            .unwrap();
 
-                    write!(
-                        self.stdout,
-                        "{}{:04X}",
-                        termion::cursor::Goto(6 * c + 75, l + 2),
-                        cmd
-                    )
-                    .unwrap();
-                }
+            for c in 0..8 {
+                self.draw_word((offset + l) * 16 + 2 * c);
             }
         }
+    }
 
-        if let Some(old_pc) = self.pc {
-            let line = self.pc.unwrap() / 16;
+    /// Draw the word at `addr` if it is in view, highlighting the PC,
+    /// breakpoints and the cursor
+    fn draw_word(&mut self, addr: u16) {
+        let offset = match self.prog_offset {
+            Some(offset) => offset,
+            None => return,
+        };
+        let line = addr / 16;
+        if line < offset || line >= offset + self.height {
+            return;
+        }
 
-            if self.prog_offset.unwrap() <= line || line < (self.prog_offset.unwrap() + 32) {
-                let col = (old_pc % 16) * 3 + 75;
-                let cmd = ((self.prog[old_pc as usize] as u16) << 8)
-                    + (self.prog[(old_pc + 1) as usize] as u16);
-                write!(
-                    self.stdout,
-                    "{}{:04X}",
-                    termion::cursor::Goto(col, line - self.prog_offset.unwrap() + 2),
-                    cmd
-                )
-                .unwrap();
-            }
+        let cmd = ((self.prog[addr as usize] as u16) << 8)
+            + (self.prog[(addr as usize + 1) % self.prog.len()] as u16);
+        let mut styles = self.theme.ram.fg();
+        if self.pc == Some(addr) {
+            styles.push_str(&self.theme.pc.highlight());
+        }
+        if self.breakpoints.contains(&addr) {
+            styles.push_str(&self.theme.breakpoint.bg());
+        }
+        if self.cursor == Some(addr) {
+            styles.push_str(style::Underline.as_ref());
         }
 
-        self.pc = Some(pc);
-        let line = self.pc.unwrap() / 16;
-        let col = (self.pc.unwrap() % 16) * 3 + 75;
-        let cmd = ((self.prog[self.pc.unwrap() as usize] as u16) << 8)
-            + (prog[(self.pc.unwrap() + 1) as usize] as u16);
         write!(
             self.stdout,
             "{}{}{:04X}{}",
-            termion::cursor::Goto(col, line - self.prog_offset.unwrap() + 2),
-            style::Invert,
+            termion::cursor::Goto((addr % 16) * 3 + self.width + 11, line - offset + 2),
+            styles,
             cmd,
-            style::NoInvert
+            style::Reset
         )
         .unwrap();
     }
 
+    /// Move the RAM pane cursor by `delta` bytes, scrolling it into view
+    fn move_cursor(&mut self, delta: i32) {
+        let start = self.cursor.unwrap_or(self.pc.unwrap_or(0) & !1);
+        let addr = (start as i32 + delta).clamp(0, self.prog.len() as i32 - 2) as u16;
+        let old = self.cursor.replace(addr);
+        if let Some(old) = old {
+            self.draw_word(old);
+        }
+
+        let line = addr / 16;
+        match self.prog_offset {
+            Some(offset) if line < offset => {
+                self.prog_offset = Some(line);
+                self.draw_rows();
+            }
+            Some(offset) if line >= offset + self.height => {
+                self.prog_offset = Some(line - (self.height - 1));
+                self.draw_rows();
+            }
+            _ => self.draw_word(addr),
+        }
+    }
+
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::Quit => self.running = false,
+            Action::Pause => self.commands.push(Command::TogglePause),
+            Action::Step => self.commands.push(Command::Step),
+            Action::StepOver => self.commands.push(Command::StepOver),
+            Action::StepOut => self.commands.push(Command::StepOut),
+            Action::ToggleBreakpoint => {
+                let addr = self.cursor.or(self.pc).unwrap_or(0);
+                self.commands.push(Command::ToggleBreakpoint(addr));
+            }
+            Action::RunToCursor => {
+                if let Some(addr) = self.cursor {
+                    self.commands.push(Command::RunTo(addr));
+                }
+            }
+            Action::CursorLeft => self.move_cursor(-2),
+            Action::CursorRight => self.move_cursor(2),
+            Action::CursorUp => self.move_cursor(-16),
+            Action::CursorDown => self.move_cursor(16),
+            Action::Rewind => self.commands.push(Command::Rewind),
+            Action::Reset => self.commands.push(Command::Reset),
+            Action::SpeedUp => self.commands.push(Command::SpeedUp),
+            Action::SpeedDown => self.commands.push(Command::SpeedDown),
+            Action::SaveState(slot) => self.commands.push(Command::SaveState(slot)),
+            Action::LoadState(slot) => self.commands.push(Command::LoadState(slot)),
+        }
+    }
+
     fn draw_values(&mut self, pc: u16, ireg: u16, delay: u8, sound: u8) {
-        write!(self.stdout, "{}{:04X}", termion::cursor::Goto(72, 35), pc).unwrap();
-        write!(self.stdout, "{}{:04X}", termion::cursor::Goto(72, 36), ireg).unwrap();
+        let (row, col) = (self.height + 3, self.width + 8);
+        write!(self.stdout, "{}{:04X}", termion::cursor::Goto(col, row), pc).unwrap();
+        write!(
+            self.stdout,
+            "{}{:04X}",
+            termion::cursor::Goto(col, row + 1),
+            ireg
+        )
+        .unwrap();
         write!(
             self.stdout,
             "{}{:02X}",
-            termion::cursor::Goto(74, 37),
+            termion::cursor::Goto(col + 2, row + 2),
             delay
         )
         .unwrap();
         write!(
             self.stdout,
-            "{}{:02X}",
-            termion::cursor::Goto(74, 38),
+            "{}{}{:02X}",
+            termion::cursor::Goto(col, row + 3),
+            if sound > 0 { "♪ " } else { "  " },
             sound
         )
         .unwrap();
@@ -313,23 +438,35 @@ impl TUI {
 
     fn draw_registers(&mut self, register_bank: &[u8]) {
         for i in 0..16 {
-            let row = (i / 4) + 35;
-            let col = (i % 4) * 6 + 78;
+            let row = (i / 4) + self.height + 3;
+            let col = (i % 4) * 6 + self.width + 14;
+            let val = register_bank[i as usize];
+            let changed = self
+                .registers
+                .get(i as usize)
+                .is_some_and(|old| *old != val);
+            let style = match changed {
+                true => self.theme.changed.fg(),
+                false => String::new(),
+            };
 
             write!(
                 self.stdout,
-                "{}{:04X}",
+                "{}{}{:04X}{}",
                 termion::cursor::Goto(col, row),
-                register_bank[i as usize]
+                style,
+                val,
+                style::Reset
             )
             .unwrap();
         }
+        self.registers = register_bank.to_vec();
     }
 
     fn draw_stack(&mut self, stack: &[u16], len: usize) {
         for (i, val) in stack.iter().enumerate().take(16) {
-            let row = ((i / 4) + 35) as u16;
-            let col = ((i % 4) * 6 + 101) as u16;
+            let row = (i / 4) as u16 + self.height + 3;
+            let col = (i % 4) as u16 * 6 + self.width + 37;
 
             if i < len {
                 write!(
@@ -346,24 +483,144 @@ impl TUI {
     }
 }
 
+impl Frontend for TUI {
+    fn update(&mut self, state: &EmulatorState) {
+        if (state.display_width, state.display_height) != self.res {
+            self.resize(state.display_width, state.display_height);
+        }
+
+        let shown = self.phosphor.apply(&state.display);
+        self.draw_display(&shown);
+        self.draw_keypad();
+        self.draw_program(&state.ram, state.pc);
+        self.draw_values(state.pc, state.ireg, state.delay_timer, state.sound_timer);
+        self.draw_registers(&state.register_bank);
+        self.draw_stack(&state.stack, state.stack_len);
+
+        self.stdout.flush().unwrap();
+    }
+
+    fn show_error(&mut self, err: &EmulatorError) {
+        let msg = format!("Error: {}", err);
+        let len = self.message_len();
+        write!(
+            self.stdout,
+            "{}{}{:<len$.len$}{}",
+            termion::cursor::Goto(8, self.height + 3),
+            style::Invert,
+            msg,
+            style::NoInvert
+        )
+        .unwrap();
+        let quit = match self.keymap.binding(Action::Quit) {
+            Some(binding) => format!("press {} to quit", binding),
+            None => "no quit key bound".to_string(),
+        };
+        write!(
+            self.stdout,
+            "{}Emulation halted, {:<len$.len$}",
+            termion::cursor::Goto(8, self.height + 4),
+            quit,
+            len = len.saturating_sub(18)
+        )
+        .unwrap();
+
+        self.stdout.flush().unwrap();
+    }
+
+    fn show_message(&mut self, msg: &str) {
+        let len = self.message_len();
+        write!(
+            self.stdout,
+            "{}{:<len$.len$}",
+            termion::cursor::Goto(8, self.height + 6),
+            msg
+        )
+        .unwrap();
+
+        self.stdout.flush().unwrap();
+    }
+
+    fn show_stats(&mut self, fps: f64, ips: f64) {
+        let stats = format!("FPS: {:5.1}  IPS: {:7.1}", fps, ips);
+        let len = self.message_len();
+        write!(
+            self.stdout,
+            "{}{:<len$.len$}",
+            termion::cursor::Goto(8, self.height + 5),
+            stats
+        )
+        .unwrap();
+
+        self.stdout.flush().unwrap();
+    }
+
+    fn show_debugger(&mut self, debugger: &Debugger) {
+        if *debugger.breakpoints() != self.breakpoints {
+            let changed: Vec<u16> = debugger
+                .breakpoints()
+                .symmetric_difference(&self.breakpoints)
+                .cloned()
+                .collect();
+            self.breakpoints.clone_from(debugger.breakpoints());
+            for addr in changed {
+                self.draw_word(addr);
+            }
+            self.stdout.flush().unwrap();
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.running
+    }
+
+    fn poll_input(&mut self) {
+        let keys: Vec<Keycode> = self.input.held();
+        let pressed: Vec<Keycode> = keys
+            .iter()
+            .filter(|k| !self.prev_pressed.contains(k))
+            .cloned()
+            .collect();
+
+        for action in self.keymap.actions(&keys, &pressed) {
+            self.run_action(action);
+        }
+        if !pressed.is_empty() {
+            self.stdout.flush().unwrap();
+        }
+        self.keys = Some(self.keymap.keypad_mask(&keys));
+        self.prev_pressed = keys;
+    }
+
+    fn take_commands(&mut self) -> Vec<Command> {
+        std::mem::take(&mut self.commands)
+    }
+
+    fn keys(&self) -> u16 {
+        self.keys.unwrap_or(0)
+    }
+}
+
 impl Drop for TUI {
     fn drop(&mut self) {
         write!(
             self.stdout,
             "{}{}",
-            termion::cursor::Goto(1, 40),
+            termion::cursor::Goto(1, self.height + 8),
             termion::cursor::Show
         )
         .unwrap();
         self.stdout.flush().unwrap();
 
-        let stdin = stdin();
-        if stdin.keys().next().is_some() {}
+        if self.keyboard.leaves_input() {
+            let stdin = stdin();
+            if stdin.keys().next().is_some() {}
+        }
     }
 }
 
 impl Default for TUI {
     fn default() -> Self {
-        Self::new()
+        Self::new(TuiOptions::default())
     }
 }
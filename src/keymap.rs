@@ -0,0 +1,438 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use device_query::Keycode;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    Ctrl,
+    Alt,
+    Shift,
+}
+
+impl Modifier {
+    const ALL: [Modifier; 3] = [Modifier::Ctrl, Modifier::Alt, Modifier::Shift];
+
+    fn is_held(self, keys: &[Keycode]) -> bool {
+        let (left, right) = match self {
+            Modifier::Ctrl => (Keycode::LControl, Keycode::RControl),
+            Modifier::Alt => (Keycode::LAlt, Keycode::RAlt),
+            Modifier::Shift => (Keycode::LShift, Keycode::RShift),
+        };
+        keys.contains(&left) || keys.contains(&right)
+    }
+}
+
+/// A key with modifiers, written like `Ctrl+Shift+F11`. Keys use the
+/// device_query names (`A`, `Key1`, `F5`, `Up`, `Backspace`, ...), single
+/// letters and digits can also be written as themselves.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Binding {
+    pub modifiers: Vec<Modifier>,
+    pub key: Keycode,
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        let modifiers = parts
+            .iter()
+            .map(|m| match m.to_ascii_lowercase().as_str() {
+                "ctrl" => Ok(Modifier::Ctrl),
+                "alt" => Ok(Modifier::Alt),
+                "shift" => Ok(Modifier::Shift),
+                _ => Err(format!("unknown modifier '{}' in '{}'", m, s)),
+            })
+            .collect::<Result<_, _>>()?;
+        let key = match key.chars().collect::<Vec<char>>()[..] {
+            [c] if c.is_ascii_digit() => format!("Key{}", c),
+            [c] => c.to_ascii_uppercase().to_string(),
+            _ => key.to_string(),
+        };
+        let key = key
+            .parse()
+            .map_err(|_| format!("unknown key '{}' in '{}'", key, s))?;
+        Ok(Binding { modifiers, key })
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for m in self.modifiers.iter() {
+            write!(f, "{:?}+", m)?;
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Binding {
+    fn new(modifiers: &[Modifier], key: Keycode) -> Self {
+        Self {
+            modifiers: modifiers.to_vec(),
+            key,
+        }
+    }
+
+    /// Held with exactly its modifiers, or for `loose` bindings with at
+    /// least its modifiers plus any Shift
+    fn matches(&self, keys: &[Keycode], loose: bool) -> bool {
+        keys.contains(&self.key)
+            && Modifier::ALL.iter().all(|m| {
+                let wanted = self.modifiers.contains(m);
+                wanted == m.is_held(keys) || (loose && !wanted && *m == Modifier::Shift)
+            })
+    }
+}
+
+/// Emulator commands that can be bound to keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Pause,
+    Step,
+    StepOver,
+    StepOut,
+    RunToCursor,
+    ToggleBreakpoint,
+    CursorLeft,
+    CursorRight,
+    CursorUp,
+    CursorDown,
+    /// Acts every frame while held
+    Rewind,
+    Reset,
+    SpeedUp,
+    SpeedDown,
+    SaveState(u8),
+    LoadState(u8),
+}
+
+const SLOT_KEYS: [Keycode; 10] = [
+    Keycode::Key0,
+    Keycode::Key1,
+    Keycode::Key2,
+    Keycode::Key3,
+    Keycode::Key4,
+    Keycode::Key5,
+    Keycode::Key6,
+    Keycode::Key7,
+    Keycode::Key8,
+    Keycode::Key9,
+];
+
+/// Keymap file contents, anything given replaces the bindings it names
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+    #[serde(default)]
+    keypad: std::collections::BTreeMap<String, Vec<Binding>>,
+    #[serde(default)]
+    commands: CommandBindings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandBindings {
+    quit: Option<Vec<Binding>>,
+    pause: Option<Vec<Binding>>,
+    step: Option<Vec<Binding>>,
+    step_over: Option<Vec<Binding>>,
+    step_out: Option<Vec<Binding>>,
+    run_to_cursor: Option<Vec<Binding>>,
+    toggle_breakpoint: Option<Vec<Binding>>,
+    cursor_left: Option<Vec<Binding>>,
+    cursor_right: Option<Vec<Binding>>,
+    cursor_up: Option<Vec<Binding>>,
+    cursor_down: Option<Vec<Binding>>,
+    rewind: Option<Vec<Binding>>,
+    reset: Option<Vec<Binding>>,
+    speed_up: Option<Vec<Binding>>,
+    speed_down: Option<Vec<Binding>>,
+    /// Held with a digit to save to that slot
+    save_modifiers: Option<Vec<Modifier>>,
+    /// Held with a digit to load from that slot
+    load_modifiers: Option<Vec<Modifier>>,
+}
+
+impl<'de> Deserialize<'de> for Modifier {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.to_ascii_lowercase().as_str() {
+            "ctrl" => Ok(Modifier::Ctrl),
+            "alt" => Ok(Modifier::Alt),
+            "shift" => Ok(Modifier::Shift),
+            _ => Err(serde::de::Error::custom(format!(
+                "unknown modifier '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Which keys press which hex key, and which run emulator commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    /// Bindings of hex keys 0-F
+    pub keypad: [Vec<Binding>; 16],
+    pub commands: Vec<(Action, Vec<Binding>)>,
+    pub save_modifiers: Vec<Modifier>,
+    pub load_modifiers: Vec<Modifier>,
+}
+
+impl Default for Keymap {
+    /// The COSMAC VIP keypad on the left of a QWERTY keyboard
+    fn default() -> Self {
+        use Keycode::*;
+        let keypad = [X, Key1, Key2, Key3, Q, W, E, A, S, D, Z, C, Key4, R, F, V];
+        let plain = |key| vec![Binding::new(&[], key)];
+        Self {
+            keypad: keypad.map(plain),
+            commands: vec![
+                (Action::Quit, vec![Binding::new(&[Modifier::Ctrl], Q)]),
+                (Action::Pause, plain(F5)),
+                (Action::Step, plain(F11)),
+                (Action::StepOver, plain(F10)),
+                (Action::StepOut, vec![Binding::new(&[Modifier::Shift], F11)]),
+                (Action::RunToCursor, plain(F4)),
+                (Action::ToggleBreakpoint, plain(F9)),
+                (Action::CursorLeft, plain(Left)),
+                (Action::CursorRight, plain(Right)),
+                (Action::CursorUp, plain(Up)),
+                (Action::CursorDown, plain(Down)),
+                (Action::Rewind, plain(Backspace)),
+                (Action::Reset, vec![Binding::new(&[Modifier::Ctrl], R)]),
+                (Action::SpeedUp, plain(F8)),
+                (Action::SpeedDown, plain(F7)),
+            ],
+            save_modifiers: vec![Modifier::Ctrl],
+            load_modifiers: vec![Modifier::Alt],
+        }
+    }
+}
+
+impl Keymap {
+    /// Apply a keymap file on top of this one
+    pub fn load(&mut self, fname: &str) -> Result<(), Box<dyn Error>> {
+        self.apply(&fs::read_to_string(fname)?)
+            .map_err(|err| format!("{}: {}", fname, err).into())
+    }
+
+    /// Apply TOML such as
+    ///
+    /// ```toml
+    /// [keypad]
+    /// 5 = ["w", "Up"]
+    ///
+    /// [commands]
+    /// pause = ["Space"]
+    /// ```
+    pub fn apply(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        let file: KeymapFile = toml::from_str(text)?;
+        for (name, bindings) in file.keypad {
+            let key = u8::from_str_radix(&name, 16)
+                .ok()
+                .filter(|k| *k < 16)
+                .ok_or_else(|| format!("invalid hex key '{}'", name))?;
+            self.keypad[key as usize] = bindings;
+        }
+
+        let c = file.commands;
+        let overrides = [
+            (Action::Quit, c.quit),
+            (Action::Pause, c.pause),
+            (Action::Step, c.step),
+            (Action::StepOver, c.step_over),
+            (Action::StepOut, c.step_out),
+            (Action::RunToCursor, c.run_to_cursor),
+            (Action::ToggleBreakpoint, c.toggle_breakpoint),
+            (Action::CursorLeft, c.cursor_left),
+            (Action::CursorRight, c.cursor_right),
+            (Action::CursorUp, c.cursor_up),
+            (Action::CursorDown, c.cursor_down),
+            (Action::Rewind, c.rewind),
+            (Action::Reset, c.reset),
+            (Action::SpeedUp, c.speed_up),
+            (Action::SpeedDown, c.speed_down),
+        ];
+        for (action, bindings) in overrides {
+            if let Some(bindings) = bindings {
+                match self.commands.iter_mut().find(|(a, _)| *a == action) {
+                    Some((_, old)) => *old = bindings,
+                    None => self.commands.push((action, bindings)),
+                }
+            }
+        }
+        if let Some(modifiers) = c.save_modifiers {
+            self.save_modifiers = modifiers;
+        }
+        if let Some(modifiers) = c.load_modifiers {
+            self.load_modifiers = modifiers;
+        }
+        Ok(())
+    }
+
+    /// Bind a key to a hex key on top of the existing bindings, taking it
+    /// away from any command it runs on its own
+    pub fn bind_keypad(&mut self, key: Keycode, hex: u8) {
+        let binding = Binding::new(&[], key);
+        for (_, bindings) in self.commands.iter_mut() {
+            bindings.retain(|b| *b != binding);
+        }
+        self.keypad[hex as usize].push(binding);
+    }
+
+    /// The first key bound to an action, for help text
+    pub fn binding(&self, action: Action) -> Option<&Binding> {
+        self.commands
+            .iter()
+            .find(|(a, _)| *a == action)
+            .and_then(|(_, bindings)| bindings.first())
+    }
+
+    /// Mask of the hex keys held, bit N for key N
+    pub fn keypad_mask(&self, keys: &[Keycode]) -> u16 {
+        self.keypad
+            .iter()
+            .enumerate()
+            .filter(|(_, bindings)| bindings.iter().any(|b| b.matches(keys, true)))
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+
+    /// Commands triggered this frame. `pressed` are the keys that went down
+    /// since the last frame, held actions such as rewind repeat every frame.
+    pub fn actions(&self, keys: &[Keycode], pressed: &[Keycode]) -> Vec<Action> {
+        let mut actions: Vec<Action> = self
+            .commands
+            .iter()
+            .filter(|(action, bindings)| {
+                bindings.iter().any(|b| {
+                    b.matches(keys, false)
+                        && (*action == Action::Rewind || pressed.contains(&b.key))
+                })
+            })
+            .map(|(action, _)| *action)
+            .collect();
+
+        for (slot, key) in SLOT_KEYS.iter().enumerate() {
+            if !pressed.contains(key) {
+                continue;
+            }
+            let save = Binding::new(&self.save_modifiers, *key);
+            let load = Binding::new(&self.load_modifiers, *key);
+            if save.matches(keys, false) {
+                actions.push(Action::SaveState(slot as u8));
+            } else if load.matches(keys, false) {
+                actions.push(Action::LoadState(slot as u8));
+            }
+        }
+        actions
+    }
+}
+
+/// Per-ROM keymap, kept next to the program like its save slots
+pub fn rom_keymap_path(program: &str) -> String {
+    format!("{}.keymap.toml", program)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_binding() {
+        assert_eq!("q".parse(), Ok(Binding::new(&[], Keycode::Q)));
+        assert_eq!("4".parse(), Ok(Binding::new(&[], Keycode::Key4)));
+        assert_eq!(
+            "Ctrl+Shift+F11".parse(),
+            Ok(Binding::new(
+                &[Modifier::Ctrl, Modifier::Shift],
+                Keycode::F11
+            ))
+        );
+        assert_eq!(
+            "ctrl+shift+F11".parse::<Binding>().unwrap().to_string(),
+            "Ctrl+Shift+F11"
+        );
+        assert!("Hyper+Q".parse::<Binding>().is_err());
+        assert!("Nope".parse::<Binding>().is_err());
+    }
+
+    #[test]
+    fn test_default_keymap() {
+        use Keycode::*;
+        let keymap = Keymap::default();
+        assert_eq!(keymap.keypad_mask(&[X, Key1, V]), 0x8003);
+        // Shift doesn't get in the way of the keypad, Ctrl does
+        assert_eq!(keymap.keypad_mask(&[LShift, W]), 1 << 5);
+        assert_eq!(keymap.keypad_mask(&[LControl, W]), 0);
+
+        assert_eq!(keymap.actions(&[F11], &[F11]), vec![Action::Step]);
+        assert_eq!(
+            keymap.actions(&[LShift, F11], &[F11]),
+            vec![Action::StepOut]
+        );
+        assert_eq!(keymap.actions(&[F11], &[]), vec![]);
+        assert_eq!(keymap.actions(&[Backspace], &[]), vec![Action::Rewind]);
+        assert_eq!(keymap.actions(&[RControl, Q], &[Q]), vec![Action::Quit]);
+        assert_eq!(
+            keymap.actions(&[LControl, Key3], &[Key3]),
+            vec![Action::SaveState(3)]
+        );
+        assert_eq!(
+            keymap.actions(&[LAlt, Key3], &[Key3]),
+            vec![Action::LoadState(3)]
+        );
+    }
+
+    #[test]
+    fn test_keymap_file() {
+        use Keycode::*;
+        let mut keymap = Keymap::default();
+        keymap
+            .apply(
+                "
+                [keypad]
+                5 = [\"z\", \"Up\"]
+                A = [\"Space\"]
+
+                [commands]
+                pause = [\"p\", \"F5\"]
+                cursor_up = []
+                load_modifiers = [\"Ctrl\", \"Shift\"]
+                ",
+            )
+            .unwrap();
+        assert_eq!(keymap.keypad_mask(&[Up]), 1 << 5);
+        assert_eq!(keymap.keypad_mask(&[Z]), 1 << 5);
+        assert_eq!(keymap.keypad_mask(&[W]), 0);
+        assert_eq!(keymap.keypad_mask(&[Space]), 1 << 0xA);
+        assert_eq!(keymap.actions(&[P], &[P]), vec![Action::Pause]);
+        assert_eq!(keymap.actions(&[Up], &[Up]), vec![]);
+        assert_eq!(
+            keymap.actions(&[LControl, LShift, Key1], &[Key1]),
+            vec![Action::LoadState(1)]
+        );
+
+        keymap.bind_keypad(Down, 8);
+        assert_eq!(keymap.keypad_mask(&[Down]), 1 << 8);
+        assert_eq!(keymap.actions(&[Down], &[Down]), vec![]);
+
+        assert!(keymap.apply("[keypad]\nG = [\"q\"]").is_err());
+        assert!(keymap.apply("[commands]\nexplode = [\"q\"]").is_err());
+    }
+}
@@ -1,61 +1,413 @@
 use std::error::Error;
-use std::{thread, time};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::config::Config;
+use crate::audio::AudioSink;
+use crate::config::{Commands, Config};
+use crate::debugger::Debugger;
 use crate::emulator::Emulator;
-use crate::file_io::read_program;
-use crate::interface::TUI;
+use crate::file_io::{read_program, read_state, state_slot_path, write_state};
+use crate::frontend::{Command, Frontend};
+use crate::interface::{TuiOptions, TUI};
+use crate::keymap::{rom_keymap_path, Keymap};
+use crate::movie::Movie;
+use crate::rewind::Rewind;
+use crate::theme::Theme;
+use crate::timing::{CycleBudget, FrameClock, RateMeter, FRAME_RATE};
+use crate::trace::Tracer;
 
+pub mod assembler;
+pub mod audio;
 pub mod config;
+pub mod debugger;
+pub mod disasm;
 pub mod emulator;
 pub mod file_io;
+pub mod frontend;
+pub mod headless;
+pub mod input;
 pub mod interface;
-
-type SchedulerJob = Box<dyn Fn(&mut Emulator, &mut TUI)>;
+pub mod keymap;
+pub mod movie;
+mod ops;
+pub mod quirks;
+pub mod render;
+pub mod rewind;
+pub mod romdb;
+pub mod theme;
+pub mod timing;
+pub mod trace;
+pub mod tracediff;
 
 pub fn run(cfg: Config) -> Result<(), Box<dyn Error>> {
-    let mut tui = TUI::new();
+    match &cfg.command {
+        Some(Commands::Asm { source, output }) => return assembler::run(source, output.as_deref()),
+        Some(Commands::Disasm {
+            rom,
+            syntax,
+            linear,
+        }) => return disasm::run(rom, *syntax, *linear, &cfg.quirks.into()),
+        Some(Commands::Tracediff { a, b, context }) => return tracediff::run(a, b, *context),
+        Some(Commands::Config) => {
+            println!(
+                "# Config file: {}",
+                cfg.config_source.as_deref().unwrap_or("none")
+            );
+            if let Some(rom) = &cfg.rom {
+                println!("# ROM: {}", rom.title);
+            }
+            print!("{}", toml::to_string(&cfg.effective())?);
+            return Ok(());
+        }
+        None => {}
+    }
+    if cfg.headless {
+        return headless::run(&cfg);
+    }
+
+    let theme = match &cfg.theme_file {
+        Some(fname) => Theme::load(fname, cfg.theme)?,
+        None => cfg.theme.into(),
+    };
+    // Files the user wrote win over the database's bindings
+    let mut keymap = Keymap::default();
+    for (key, hex) in cfg.rom.iter().flat_map(|rom| rom.keys.iter()) {
+        keymap.bind_keypad(*key, *hex);
+    }
+    if let Some(fname) = &cfg.keymap {
+        keymap.load(fname)?;
+    }
+    if let Some(program) = &cfg.program {
+        let fname = rom_keymap_path(program);
+        if Path::new(&fname).exists() {
+            keymap.load(&fname)?;
+        }
+    }
+    let mut tui = TUI::new(TuiOptions {
+        render: cfg.render,
+        persistence: cfg.persistence,
+        theme,
+        keyboard: cfg.keyboard,
+        key_hold: Duration::from_millis(cfg.key_hold),
+        keymap,
+        title: cfg.rom.as_ref().map(|rom| rom.title.clone()),
+    });
     tui.init_tui();
 
-    let mut ch8 = Emulator::new();
-    if let Some(fname) = cfg.program {
-        ch8.load_prog(&read_program(&fname)?)?;
-        tui.update_tui(ch8.get_state());
+    let mut audio = cfg.audio.open(cfg.audio_file.as_deref())?;
+    run_frontend(&cfg, &mut tui, audio.as_mut())
+}
+
+/// Range of the speed hotkeys, as a multiple of the configured speed
+const MIN_SPEED: f64 = 0.125;
+const MAX_SPEED: f64 = 16.;
+
+/// Main loop shared by all interactive frontends
+pub fn run_frontend<F: Frontend>(
+    cfg: &Config,
+    frontend: &mut F,
+    audio: &mut dyn AudioSink,
+) -> Result<(), Box<dyn Error>> {
+    let replay = cfg.replay.as_deref().map(Movie::load).transpose()?;
+    let (mut ch8, prog) = new_emulator(cfg, replay.as_ref())?;
+    let ips_target = replay
+        .as_ref()
+        .map_or(cfg.instructions_per_second(), |m| m.ips);
+    let mut recording = cfg
+        .record
+        .as_ref()
+        .map(|_| Movie::new(&prog, ch8.get_state().rng, cfg.quirks, ips_target));
+    frontend.update(ch8.get_state());
+    if replay.is_some() {
+        frontend.show_message("Replaying");
     }
 
-    let em_freq: u32 = match cfg.frequency {
-        Some(f) => (1000. / f) as u32,
-        None => 10,
+    let mut clock = FrameClock::new(Instant::now());
+    let mut speed = 1.;
+    let mut budget = CycleBudget::new(ips_target);
+    let mut fps = RateMeter::new(Instant::now());
+    let mut ips = RateMeter::new(Instant::now());
+    let mut debugger = Debugger::new();
+    for bp in cfg.breakpoints.iter() {
+        debugger.add_breakpoint(bp.clone());
+    }
+    for cond in cfg.break_when.iter() {
+        debugger.add_break_when(cond.clone());
+    }
+    for watch in cfg.watch.iter() {
+        debugger.add_watchpoint(watch.clone());
+    }
+    ch8.set_access_log(debugger.watches_ram());
+    let mut tracer = match &cfg.trace {
+        Some(fname) => Some(Tracer::create(fname, cfg.trace_filter())?),
+        None => None,
     };
+    // A movie can only be played back from where it started, and every
+    // frame of it has to run the full budget
+    let movie = replay.is_some() || recording.is_some();
+    if cfg.paused && !movie {
+        debugger.pause(ch8.get_state());
+    }
 
-    let sched: Vec<(u32, SchedulerJob)> = vec![
-        (
-            17,
-            Box::new(|em, t| {
-                t.update_keys();
-                t.update_tui(em.get_state())
-            }),
-        ),
-        (17, Box::new(|em, _t| em.tick())),
-        (em_freq, Box::new(|em, t| em.step(t.get_keys()))),
-    ];
+    let mut rewind = Rewind::with_seconds(cfg.rewind);
+    let mut result = Ok(());
+    let mut movie_frame = 0;
+    while frontend.is_running() && result.is_ok() {
+        let frames = clock.due(Instant::now());
+        for _ in 0..frames {
+            frontend.poll_input();
+            let mut rewound = false;
+            for cmd in frontend.take_commands() {
+                match cmd {
+                    Command::LoadState(_)
+                    | Command::Rewind
+                    | Command::Reset
+                    | Command::SpeedUp
+                    | Command::SpeedDown
+                    | Command::TogglePause
+                    | Command::Step
+                    | Command::StepOver
+                    | Command::StepOut
+                    | Command::RunTo(_)
+                    | Command::ToggleBreakpoint(_)
+                        if movie =>
+                    {
+                        frontend.show_message("Not available while using a movie");
+                        continue;
+                    }
+                    Command::Rewind => {
+                        match rewind.pop().map(|state| ch8.load_state(&state)) {
+                            Some(Ok(())) => {
+                                frontend.show_message(&format!("Rewinding ({})", rewind.len()));
+                            }
+                            Some(Err(err)) => {
+                                frontend.show_message(&format!("Rewind failed: {}", err))
+                            }
+                            None => frontend.show_message("Nothing to rewind"),
+                        }
+                        rewound = true;
+                        continue;
+                    }
+                    Command::Reset => {
+                        match new_emulator(cfg, None) {
+                            Ok((reset, _)) => {
+                                ch8 = reset;
+                                ch8.set_access_log(debugger.watches_ram());
+                                rewind.clear();
+                                frontend.show_message("Reset");
+                            }
+                            Err(err) => frontend.show_message(&format!("Reset failed: {}", err)),
+                        }
+                        continue;
+                    }
+                    Command::SpeedUp | Command::SpeedDown => {
+                        speed = match cmd {
+                            Command::SpeedUp => speed * 2.,
+                            _ => speed / 2.,
+                        };
+                        speed = f64::clamp(speed, MIN_SPEED, MAX_SPEED);
+                        budget = CycleBudget::new(ips_target * speed);
+                        frontend.show_message(&format!(
+                            "Speed {}x ({} IPS)",
+                            speed,
+                            ips_target * speed
+                        ));
+                        continue;
+                    }
+                    _ => {}
+                }
+                run_command(
+                    cmd,
+                    cfg.program.as_deref(),
+                    cfg.save_dir.as_deref(),
+                    &mut ch8,
+                    &mut debugger,
+                    frontend,
+                );
+            }
 
-    let mut prod = 1;
-    for job in sched.iter() {
-        prod *= job.0;
-    }
+            if rewound {
+                continue;
+            }
+            if !debugger.is_paused() {
+                rewind.push(ch8.get_state());
+            }
+
+            // Movies hold the keys of every frame that ran unpaused
+            let keys = match &replay {
+                Some(movie) if !debugger.is_paused() => match movie.keys(movie_frame) {
+                    Some(keys) => keys,
+                    None => {
+                        if movie_frame == movie.frames.len() {
+                            frontend.show_message("Replay finished");
+                        }
+                        frontend.keys()
+                    }
+                },
+                _ => frontend.keys(),
+            };
+            if !debugger.is_paused() {
+                movie_frame += 1;
+                if let Some(movie) = recording.as_mut() {
+                    movie.push(keys);
+                }
+            }
+
+            // While paused only a requested single step runs, and the timers
+            // stay frozen
+            let cycles = budget.next_frame();
+            let cycles = if debugger.is_paused() { 1 } else { cycles };
+            let mut executed = 0;
+            for _ in 0..cycles {
+                if !movie && !debugger.before_step(&ch8) {
+                    break;
+                }
+                if let Some(tracer) = tracer.as_mut() {
+                    tracer.before_step(&ch8);
+                }
+                match ch8.step(keys) {
+                    Ok(outcome) => {
+                        if let Some(tracer) = tracer.as_mut() {
+                            tracer.after_step(&ch8, outcome);
+                        }
+                    }
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                }
+                if !movie {
+                    debugger.after_step(&ch8);
+                }
+                executed += 1;
+            }
+            if !debugger.is_paused() {
+                audio.frame(ch8.get_state());
+                ch8.tick();
+            }
+            ips.add(executed, Instant::now());
+            if let Some(stop) = debugger.take_stop() {
+                frontend.show_message(&stop);
+            }
 
-    let mut cnt: u32 = 0;
-    while tui.is_running() {
-        for job in sched.iter() {
-            if cnt % job.0 == 0 {
-                job.1(&mut ch8, &mut tui);
+            if result.is_err() {
+                break;
             }
         }
 
-        cnt = (cnt + 1) % prod;
-        thread::sleep(time::Duration::from_millis(1));
+        if frames > 0 {
+            frontend.update(ch8.get_state());
+            frontend.show_debugger(&debugger);
+            fps.add(1, Instant::now());
+            frontend.show_stats(fps.rate(), ips.rate());
+        }
+
+        let now = Instant::now();
+        if clock.deadline() > now {
+            thread::sleep(clock.deadline() - now);
+        }
+    }
+
+    // A failed trace shouldn't cost the recording, so everything gets
+    // written before the first error is returned
+    let mut finished = Vec::new();
+    if let Some(tracer) = tracer {
+        finished.push(tracer.finish().map(|_| ()).map_err(|err| err.into()));
+    }
+    finished.push(audio.finish());
+    if let (Some(fname), Some(movie)) = (&cfg.record, &recording) {
+        finished.push(movie.save(fname));
+    }
+
+    // Leave the final state on screen until the user quits
+    if let Err(err) = &result {
+        frontend.update(ch8.get_state());
+        frontend.show_error(err);
+        while frontend.is_running() {
+            frontend.poll_input();
+            thread::sleep(Duration::from_secs_f64(1. / FRAME_RATE));
+        }
     }
 
-    Ok(())
+    finished.into_iter().collect::<Result<(), _>>()?;
+    Ok(result?)
+}
+
+/// Create an emulator with the program, seed and save state from the config,
+/// or the quirks and seed of a movie being replayed. Returns the program too.
+pub(crate) fn new_emulator(
+    cfg: &Config,
+    replay: Option<&Movie>,
+) -> Result<(Emulator, Vec<u8>), Box<dyn Error>> {
+    let mut ch8 = Emulator::new(replay.map_or(cfg.quirks, |m| m.quirks).into());
+    if let Some(seed) = replay.map(|m| m.seed).or(cfg.seed) {
+        ch8.seed_rng(seed);
+    }
+    let prog = match &cfg.program {
+        Some(fname) => read_program(fname)?,
+        None => Vec::new(),
+    };
+    if let Some(movie) = replay {
+        movie.check_rom(&prog)?;
+    }
+    if cfg.program.is_some() {
+        ch8.load_prog(&prog)?;
+    }
+    if let Some(fname) = &cfg.load_state {
+        ch8.load_state(&read_state(fname)?)?;
+    }
+    Ok((ch8, prog))
+}
+
+fn run_command<F: Frontend>(
+    cmd: Command,
+    program: Option<&str>,
+    save_dir: Option<&str>,
+    em: &mut Emulator,
+    debugger: &mut Debugger,
+    t: &mut F,
+) {
+    match cmd {
+        Command::SaveState(slot) => {
+            let path = state_slot_path(program, save_dir, slot);
+            let res = match save_dir {
+                Some(dir) => fs::create_dir_all(dir).map_err(|err| err.into()),
+                None => Ok(()),
+            };
+            match res.and_then(|_| write_state(&path, em.get_state())) {
+                Ok(()) => t.show_message(&format!("Saved slot {} to {}", slot, path)),
+                Err(err) => t.show_message(&format!("Save failed: {}", err)),
+            }
+        }
+        Command::LoadState(slot) => {
+            let path = state_slot_path(program, save_dir, slot);
+            let res = read_state(&path).and_then(|s| Ok(em.load_state(&s)?));
+            match res {
+                Ok(()) => t.show_message(&format!("Loaded slot {} from {}", slot, path)),
+                Err(err) => t.show_message(&format!("Load failed: {}", err)),
+            }
+        }
+        Command::TogglePause => {
+            debugger.toggle_pause(em.get_state());
+            if !debugger.is_paused() {
+                t.show_message("Running");
+            }
+        }
+        Command::Step => debugger.step(),
+        Command::StepOver => debugger.step_over(em.get_state()),
+        Command::StepOut => {
+            if !debugger.step_out(em.get_state()) {
+                t.show_message("Not in a subroutine");
+            }
+        }
+        Command::RunTo(addr) => debugger.run_to(addr),
+        Command::ToggleBreakpoint(addr) => match debugger.toggle_breakpoint(addr) {
+            true => t.show_message(&format!("Breakpoint set at {:04X}", addr)),
+            false => t.show_message(&format!("Breakpoint cleared at {:04X}", addr)),
+        },
+        // Need the main loop's state, handled there
+        Command::Rewind | Command::Reset | Command::SpeedUp | Command::SpeedDown => {}
+    }
 }
@@ -1,12 +1,11 @@
-use clap::Parser;
-use std::error::Error;
+use std::process;
 
 use chip_8::config::Config;
 use chip_8::run;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let cfg = Config::parse();
-    run(cfg)?;
-
-    Ok(())
+fn main() {
+    if let Err(err) = Config::load().and_then(run) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,291 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+
+use clap::ValueEnum;
+
+use crate::headless::InputEvent;
+use crate::quirks::QuirksPreset;
+
+const MOVIE_HEADER: &str = "chip-8 movie 1";
+
+/// Longest movie accepted, a day at 60 frames per second
+const MAX_FRAMES: usize = 60 * 60 * 60 * 24;
+
+/// Key masks fed to the emulator, one per frame, along with everything else
+/// needed to play them back: the ROM, the RNG seed, the quirks and the speed.
+///
+/// Movies are plain text, frames are run length encoded:
+///
+/// ```text
+/// chip-8 movie 1
+/// rom 159ba69f4c40be3042fc54c7fbb2025f7e49f8e0
+/// seed 1234
+/// quirks default
+/// ips 600
+/// 0000 120
+/// 0020 8
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    /// SHA-1 of the program, in hex
+    pub rom: String,
+    pub seed: u64,
+    pub quirks: QuirksPreset,
+    /// Instructions per second
+    pub ips: f64,
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    pub fn new(prog: &[u8], seed: u64, quirks: QuirksPreset, ips: f64) -> Self {
+        Self {
+            rom: rom_hash(prog),
+            seed,
+            quirks,
+            ips,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(fname: &str) -> Result<Self, Box<dyn Error>> {
+        Self::decode(&fs::read_to_string(fname)?)
+    }
+
+    pub fn save(&self, fname: &str) -> Result<(), Box<dyn Error>> {
+        Ok(fs::write(fname, self.encode())?)
+    }
+
+    pub fn push(&mut self, keys: u16) {
+        self.frames.push(keys);
+    }
+
+    /// Key mask for a frame, `None` once the movie has ended
+    pub fn keys(&self, frame: usize) -> Option<u16> {
+        self.frames.get(frame).copied()
+    }
+
+    /// Refuse to play a movie back on a different ROM
+    pub fn check_rom(&self, prog: &[u8]) -> Result<(), String> {
+        let hash = rom_hash(prog);
+        if hash != self.rom {
+            return Err(format!(
+                "movie was recorded with ROM {}, loaded ROM is {}",
+                self.rom, hash
+            ));
+        }
+        Ok(())
+    }
+
+    /// The frames as a headless input script, one event per change
+    pub fn input_events(&self) -> Vec<InputEvent> {
+        let mut events: Vec<InputEvent> = Vec::new();
+        for (frame, keys) in self.frames.iter().enumerate() {
+            if events.last().is_none_or(|e| e.keys != *keys) {
+                events.push(InputEvent {
+                    frame: frame as u64,
+                    keys: *keys,
+                });
+            }
+        }
+        events
+    }
+
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{}", MOVIE_HEADER).unwrap();
+        writeln!(out, "rom {}", self.rom).unwrap();
+        writeln!(out, "seed {}", self.seed).unwrap();
+        writeln!(
+            out,
+            "quirks {}",
+            self.quirks.to_possible_value().unwrap().get_name()
+        )
+        .unwrap();
+        writeln!(out, "ips {}", self.ips).unwrap();
+
+        let mut frames = self.frames.iter().peekable();
+        while let Some(keys) = frames.next() {
+            let mut count = 1;
+            while frames.next_if_eq(&keys).is_some() {
+                count += 1;
+            }
+            writeln!(out, "{:04X} {}", keys, count).unwrap();
+        }
+        out
+    }
+
+    pub fn decode(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, l)| l.trim()) != Some(MOVIE_HEADER) {
+            return Err("not a chip-8 movie".into());
+        }
+
+        let mut header = |name: &str| -> Result<String, String> {
+            match lines.next() {
+                Some((i, line)) => match line.split_once(' ') {
+                    Some((key, val)) if key == name => Ok(val.trim().to_string()),
+                    _ => Err(format!("line {}: expected '{}'", i + 1, name)),
+                },
+                None => Err(format!("missing '{}'", name)),
+            }
+        };
+        let rom = header("rom")?;
+        let seed = header("seed")?
+            .parse()
+            .map_err(|_| "invalid seed".to_string())?;
+        let quirks = QuirksPreset::from_str(&header("quirks")?, false)?;
+        let ips = header("ips")?
+            .parse()
+            .map_err(|_| "invalid ips".to_string())?;
+
+        let mut frames = Vec::new();
+        for (i, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = line.split_once(' ').and_then(|(keys, count)| {
+                Some((
+                    u16::from_str_radix(keys, 16).ok()?,
+                    count.trim().parse::<usize>().ok()?,
+                ))
+            });
+            let (keys, count) =
+                entry.ok_or_else(|| format!("line {}: expected '<keys> <count>'", i + 1))?;
+            if count > MAX_FRAMES - frames.len() {
+                return Err(
+                    format!("line {}: movie longer than {} frames", i + 1, MAX_FRAMES).into(),
+                );
+            }
+            frames.extend(std::iter::repeat_n(keys, count));
+        }
+
+        Ok(Self {
+            rom,
+            seed,
+            quirks,
+            ips,
+            frames,
+        })
+    }
+}
+
+/// SHA-1 of a ROM in lower case hex
+pub fn rom_hash(prog: &[u8]) -> String {
+    sha1_smol::Sha1::from(prog).digest().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::audio::NullSink;
+    use crate::config::Config;
+    use crate::file_io::write_program;
+    use crate::frontend::{Command, MemoryFrontend};
+    use crate::headless::run_frames;
+    use crate::timing::CycleBudget;
+    use crate::{new_emulator, run_frontend};
+    use clap::Parser;
+
+    fn movie() -> Movie {
+        let mut movie = Movie::new(b"\x00\xE0", 42, QuirksPreset::Schip, 660.);
+        for keys in [0, 0, 0, 0x20, 0x20, 0, 0x8001] {
+            movie.push(keys);
+        }
+        movie
+    }
+
+    #[test]
+    fn test_movie_roundtrip() {
+        let movie = movie();
+        let text = movie.encode();
+        assert!(text.ends_with("0000 3\n0020 2\n0000 1\n8001 1\n"));
+        assert_eq!(Movie::decode(&text).unwrap(), movie);
+
+        assert!(Movie::decode("chip-8 movie 1\nseed 1\n").is_err());
+        assert!(Movie::decode(&text.replace("0020 2", "0020")).is_err());
+        assert!(Movie::decode(&text.replace("0020 2", "0020 18446744073709551615")).is_err());
+        assert!(Movie::decode(&format!("{}0000 {}\n", text, MAX_FRAMES - 6)).is_err());
+    }
+
+    #[test]
+    fn test_movie_check_rom() {
+        let movie = movie();
+        assert_eq!(movie.rom, "159ba69f4c40be3042fc54c7fbb2025f7e49f8e0");
+        assert!(movie.check_rom(b"\x00\xE0").is_ok());
+        assert!(movie.check_rom(b"\x00\xEE").is_err());
+        assert_eq!(
+            movie.input_events(),
+            vec![
+                InputEvent { frame: 0, keys: 0 },
+                InputEvent {
+                    frame: 3,
+                    keys: 0x20
+                },
+                InputEvent { frame: 5, keys: 0 },
+                InputEvent {
+                    frame: 6,
+                    keys: 0x8001
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_replay() {
+        // Where the sprite ends up depends on both the keypad and the RNG
+        let prog = assemble(
+            "
+            : main
+                v1 := 5
+                loop
+                    clear
+                    v3 := random 0x1F
+                    if v1 key then v2 += 1
+                    i := hex v1
+                    sprite v2 v3 5
+                again
+            ",
+        )
+        .unwrap();
+        let dir = std::env::temp_dir();
+        let rom = dir.join(format!("movie-{}.ch8", std::process::id()));
+        let rom = rom.to_str().unwrap();
+        let fname = format!("{}.movie", rom);
+        write_program(rom, &prog).unwrap();
+
+        // The debugger would stop frames short of the replay's budget
+        let cfg = Config::parse_from([
+            "chip-8", "-p", rom, "--record", &fname, "--paused", "--break", "0x202",
+        ]);
+        let mut frontend = MemoryFrontend::new(Some(20));
+        frontend.keys = 1 << 5;
+        frontend.commands = vec![Command::TogglePause, Command::Step];
+        run_frontend(&cfg, &mut frontend, &mut NullSink).unwrap();
+
+        let movie = Movie::load(&fname).unwrap();
+        assert!(frontend.error.is_none());
+        assert_eq!(movie.frames.len() as u64, frontend.frames);
+        assert!(movie.frames.iter().all(|keys| *keys == 1 << 5));
+
+        let cfg = Config::parse_from(["chip-8", "-p", rom, "--replay", &fname]);
+        let (mut ch8, _) = new_emulator(&cfg, Some(&movie)).unwrap();
+        let mut budget = CycleBudget::new(movie.ips);
+        let frames = movie.frames.len() as u64;
+        let (result, _, _) = run_frames(
+            &mut ch8,
+            &movie.input_events(),
+            &mut budget,
+            frames,
+            None,
+            None,
+            None,
+        );
+        assert!(result.is_ok());
+        assert_eq!(ch8.get_state().display, frontend.display);
+
+        std::fs::remove_file(rom).unwrap();
+        std::fs::remove_file(&fname).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Src {
+    Reg(usize),
+    Literal(u16),
+    Key,
+    IReg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) enum Ops {
+    DisplayClear,
+    DisplayUpdate(Src, Src, Src),
+    CallSubRoutine(Src),
+    ReturnSubRoutine,
+    Jump(Src),
+    JumpRelative(Src),
+    JumpEq(Src, Src),
+    JumpNeq(Src, Src),
+    Add(Src, Src, Src),
+    Sub(Src, Src, Src),
+    And(Src, Src, Src),
+    Or(Src, Src, Src),
+    Xor(Src, Src, Src),
+    LShift(Src, Src),
+    RShift(Src, Src),
+    Rand(Src, Src),
+    ReadDelay(Src),
+    GetKey(Src),
+    WriteDelay(Src),
+    WriteSound(Src),
+    GetSprite(Src),
+    BCD(Src),
+    RegDump(Src),
+    RegLoad(Src),
+    ScrollDown(Src),
+    ScrollRight,
+    ScrollLeft,
+    LowRes,
+    HighRes,
+    Exit,
+    GetBigSprite(Src),
+    FlagsDump(Src),
+    FlagsLoad(Src),
+    ScrollUp(Src),
+    RangeDump(Src, Src),
+    RangeLoad(Src, Src),
+    LongLoad(Src),
+    SelectPlanes(Src),
+    LoadAudio,
+    SetPitch(Src),
+}
+
+/// Decode one instruction, `next` is the word following the opcode which only
+/// F000 NNNN uses. Returns None for opcodes no supported platform defines.
+pub(crate) fn decode(opcode: u16, next: u16) -> Option<Ops> {
+    let op = match (opcode & 0xF000) >> 12 {
+        0x0 => match opcode & 0x0FFF {
+            0x0E0 => Ops::DisplayClear,
+            0x0EE => Ops::ReturnSubRoutine,
+            0x0C0..=0x0CF => Ops::ScrollDown(Src::Literal(opcode & 0x000F)),
+            0x0D0..=0x0DF => Ops::ScrollUp(Src::Literal(opcode & 0x000F)),
+            0x0FB => Ops::ScrollRight,
+            0x0FC => Ops::ScrollLeft,
+            0x0FD => Ops::Exit,
+            0x0FE => Ops::LowRes,
+            0x0FF => Ops::HighRes,
+            _ => return None,
+        },
+        0x1 => Ops::Jump(Src::Literal(opcode & 0x0FFF)),
+        0x2 => Ops::CallSubRoutine(Src::Literal(opcode & 0x0FFF)),
+        0x3 => Ops::JumpEq(
+            Src::Reg(((opcode & 0xF00) >> 8) as usize),
+            Src::Literal(opcode & 0x00FF),
+        ),
+        0x4 => Ops::JumpNeq(
+            Src::Reg(((opcode & 0xF00) >> 8) as usize),
+            Src::Literal(opcode & 0x00FF),
+        ),
+        0x5 => match opcode & 0x000F {
+            0x0 => Ops::JumpEq(
+                Src::Reg(((opcode & 0xF00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            0x2 => Ops::RangeDump(
+                Src::Reg(((opcode & 0xF00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            0x3 => Ops::RangeLoad(
+                Src::Reg(((opcode & 0xF00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            _ => return None,
+        },
+        0x6 => Ops::Add(
+            Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+            Src::Literal(opcode & 0x00FF),
+            Src::Literal(0),
+        ),
+        0x7 => Ops::Add(
+            Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+            Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+            Src::Literal(opcode & 0x00FF),
+        ),
+        0x8 => match opcode & 0x000F {
+            0x0 => Ops::Add(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+                Src::Literal(0),
+            ),
+            0x1 => Ops::Or(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            0x2 => Ops::And(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            0x3 => Ops::Xor(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            0x4 => Ops::Add(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            0x5 => Ops::Sub(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            0x6 => Ops::RShift(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            0x7 => Ops::Sub(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+            ),
+            0xE => Ops::LShift(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            _ => return None,
+        },
+        0x9 => match opcode & 0x000F {
+            0x0 => Ops::JumpNeq(
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+                Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            ),
+            _ => return None,
+        },
+        0xA => Ops::Add(Src::IReg, Src::Literal(opcode & 0x0FFF), Src::Literal(0)),
+        0xB => Ops::JumpRelative(Src::Literal(opcode & 0x0FFF)),
+        0xC => Ops::Rand(
+            Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+            Src::Literal(opcode & 0x00FF),
+        ),
+        0xD => Ops::DisplayUpdate(
+            Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+            Src::Reg(((opcode & 0x00F0) >> 4) as usize),
+            Src::Literal(opcode & 0x000F),
+        ),
+        0xE => match opcode & 0x00FF {
+            0x9E => Ops::JumpEq(Src::Key, Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0xA1 => Ops::JumpNeq(Src::Key, Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            _ => return None,
+        },
+        0xF if opcode == 0xF000 => Ops::LongLoad(Src::Literal(next)),
+        0xF => match opcode & 0x00FF {
+            0x01 => Ops::SelectPlanes(Src::Literal((opcode & 0x0F00) >> 8)),
+            0x02 if opcode == 0xF002 => Ops::LoadAudio,
+            0x07 => Ops::ReadDelay(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x0A => Ops::GetKey(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x15 => Ops::WriteDelay(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x18 => Ops::WriteSound(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x1E => Ops::Add(
+                Src::IReg,
+                Src::IReg,
+                Src::Reg(((opcode & 0x0F00) >> 8) as usize),
+            ),
+            0x29 => Ops::GetSprite(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x30 => Ops::GetBigSprite(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x3A => Ops::SetPitch(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x33 => Ops::BCD(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x55 => Ops::RegDump(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x65 => Ops::RegLoad(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x75 => Ops::FlagsDump(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            0x85 => Ops::FlagsLoad(Src::Reg(((opcode & 0x0F00) >> 8) as usize)),
+            _ => return None,
+        },
+        _ => unreachable!(),
+    };
+
+    Some(op)
+}
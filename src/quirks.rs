@@ -0,0 +1,114 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Behaviour switches for the opcodes that were interpreted differently
+/// across CHIP-8 implementations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place instead of shifting VY into VX
+    pub shift: bool,
+    /// FX55/FX65 leave I pointing past the last register transferred
+    pub memory_increment: bool,
+    /// With `memory_increment`, I advances by X instead of X + 1
+    pub memory_increment_x: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to zero
+    pub vf_reset: bool,
+    /// DXYN clips sprites at the screen edge instead of wrapping them
+    pub clip: bool,
+    /// DXYN blocks until the next timer tick (vertical blank), only in low
+    /// resolution as SUPER-CHIP has no wait in high resolution
+    pub display_wait: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuirksPreset {
+    /// Behaviour of this emulator before quirks were configurable
+    Default,
+    /// Original COSMAC VIP interpreter
+    Vip,
+    /// CHIP-48 on the HP-48
+    Chip48,
+    /// SUPER-CHIP 1.1
+    Schip,
+    /// XO-CHIP as implemented by Octo
+    Xochip,
+}
+
+impl Quirks {
+    pub fn cosmac_vip() -> Self {
+        Self {
+            shift: false,
+            memory_increment: true,
+            memory_increment_x: false,
+            jump_vx: false,
+            vf_reset: true,
+            clip: true,
+            display_wait: true,
+        }
+    }
+
+    pub fn chip48() -> Self {
+        Self {
+            shift: true,
+            memory_increment: true,
+            memory_increment_x: true,
+            jump_vx: true,
+            vf_reset: false,
+            clip: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn superchip() -> Self {
+        Self {
+            shift: true,
+            memory_increment: false,
+            memory_increment_x: false,
+            jump_vx: true,
+            vf_reset: false,
+            clip: true,
+            display_wait: true,
+        }
+    }
+
+    pub fn xochip() -> Self {
+        Self {
+            shift: false,
+            memory_increment: true,
+            memory_increment_x: false,
+            jump_vx: false,
+            vf_reset: false,
+            clip: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift: true,
+            memory_increment: false,
+            memory_increment_x: false,
+            jump_vx: false,
+            vf_reset: false,
+            clip: true,
+            display_wait: false,
+        }
+    }
+}
+
+impl From<QuirksPreset> for Quirks {
+    fn from(preset: QuirksPreset) -> Self {
+        match preset {
+            QuirksPreset::Default => Quirks::default(),
+            QuirksPreset::Vip => Quirks::cosmac_vip(),
+            QuirksPreset::Chip48 => Quirks::chip48(),
+            QuirksPreset::Schip => Quirks::superchip(),
+            QuirksPreset::Xochip => Quirks::xochip(),
+        }
+    }
+}
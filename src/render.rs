@@ -0,0 +1,269 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use termion::color;
+
+use crate::theme::{Theme, ThemeColor};
+
+/// How display pixels map onto terminal cells
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenderMode {
+    /// One `█` per pixel, stretched vertically by the cell shape
+    #[default]
+    Block,
+    /// Two pixels stacked in each cell with `▀▄█`
+    HalfBlock,
+    /// 2x2 pixels per cell with the quadrant block characters
+    Quadrant,
+    /// 2x4 pixels per cell with braille dots
+    Braille,
+}
+
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+/// Pixel values handed to `RenderMode::glyph` carry the XO-CHIP planes in
+/// bits 0-1 and, for unlit pixels still fading out, a level of 1-3 in bits 2-3
+const FADE_SHIFT: u8 = 2;
+
+/// Fades pixels out over a few frames after they turn off, which hides the
+/// flicker of programs that erase and redraw sprites every frame
+pub struct Phosphor {
+    /// Frames an unlit pixel stays visible, 0 disables fading
+    decay: u8,
+    /// Frames left for each pixel
+    fade: Vec<u8>,
+}
+
+impl Phosphor {
+    pub fn new(decay: u8) -> Self {
+        Self {
+            decay,
+            fade: Vec::new(),
+        }
+    }
+
+    /// Pixels to render for this frame of the emulator display
+    pub fn apply(&mut self, display: &[u8]) -> Vec<u8> {
+        if self.fade.len() != display.len() {
+            self.fade = vec![0; display.len()];
+        }
+        let decay = self.decay as u16;
+        display
+            .iter()
+            .zip(self.fade.iter_mut())
+            .map(|(px, fade)| {
+                if px & 0x03 != 0 {
+                    *fade = self.decay;
+                    return px & 0x03;
+                }
+                if *fade == 0 {
+                    return 0;
+                }
+                let level = (*fade as u16 * 3).div_ceil(decay) as u8;
+                *fade -= 1;
+                level << FADE_SHIFT
+            })
+            .collect()
+    }
+}
+
+/// Braille dot bit for a pixel of a 2x4 cell, the bottom row was added to
+/// the original six dots later so it comes last
+fn braille_dot(x: usize, y: usize) -> u32 {
+    match y {
+        3 => 0x40 << x,
+        _ => 1 << (y + 3 * x),
+    }
+}
+
+impl RenderMode {
+    /// Pixels covered by one terminal cell as (columns, rows)
+    pub fn cell_size(self) -> (usize, usize) {
+        match self {
+            RenderMode::Block => (1, 1),
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Quadrant => (2, 2),
+            RenderMode::Braille => (2, 4),
+        }
+    }
+
+    /// Terminal cells needed to show a display of the given size
+    pub fn cells(self, width: usize, height: usize) -> (usize, usize) {
+        let (cw, ch) = self.cell_size();
+        (width / cw, height / ch)
+    }
+
+    /// Text for the cell at `col`, `row`, from a display `width` pixels wide
+    pub fn glyph(
+        self,
+        display: &[u8],
+        width: usize,
+        col: usize,
+        row: usize,
+        theme: &Theme,
+    ) -> String {
+        let (cw, ch) = self.cell_size();
+        let colored = |glyph, fg, bg| colored(theme, glyph, fg, bg);
+        let px = |x: usize, y: usize| display[(row * ch + y) * width + col * cw + x] & 0x0F;
+
+        match self {
+            RenderMode::Block => match px(0, 0) >> FADE_SHIFT {
+                0 => colored('█', px(0, 0), 0),
+                1 => colored('░', 1, 0),
+                2 => colored('▒', 1, 0),
+                _ => colored('▓', 1, 0),
+            },
+            RenderMode::HalfBlock => match (px(0, 0), px(0, 1)) {
+                (0, 0) => colored(' ', 0, 0),
+                (top, bottom) if top == bottom => colored('█', top, 0),
+                (top, 0) => colored('▀', top, 0),
+                (0, bottom) => colored('▄', bottom, 0),
+                // Plane 1 has no colour of its own, so it has to be the foreground
+                (1, bottom) => colored('▀', 1, bottom),
+                (top, 1) => colored('▄', 1, top),
+                (top, bottom) => colored('▀', top, bottom),
+            },
+            RenderMode::Quadrant | RenderMode::Braille => {
+                // One colour per cell, the highest plane lit wins over any
+                // fading pixels
+                let mut bits = 0;
+                let (mut planes, mut fade) = (0, 0);
+                for y in 0..ch {
+                    for x in 0..cw {
+                        let p = px(x, y);
+                        if p != 0 {
+                            bits |= match self {
+                                RenderMode::Quadrant => 1 << (y * 2 + x),
+                                _ => braille_dot(x, y),
+                            };
+                            planes = planes.max(p & 0x03);
+                            fade = fade.max(p);
+                        }
+                    }
+                }
+                let planes = if planes != 0 { planes } else { fade };
+                let glyph = match self {
+                    _ if bits == 0 => ' ',
+                    RenderMode::Quadrant => QUADRANTS[bits as usize],
+                    _ => char::from_u32(0x2800 + bits).unwrap(),
+                };
+                colored(glyph, planes, 0)
+            }
+        }
+    }
+}
+
+/// Theme colour of a pixel value
+fn pixel_color(theme: &Theme, px: u8) -> ThemeColor {
+    match (px >> FADE_SHIFT, px & 0x03) {
+        (0, 0) => theme.background,
+        (0, 1) => theme.pixel,
+        (0, 2) => theme.plane2,
+        (0, _) => theme.plane3,
+        (level, _) => theme.fade(level),
+    }
+}
+
+/// `glyph` drawn in the colour of the `fg` pixel over the `bg` pixel, an
+/// unlit `fg` draws the background
+fn colored(theme: &Theme, glyph: char, fg: u8, bg: u8) -> String {
+    let (glyph, fg) = match fg {
+        0 => (' ', ThemeColor::Default),
+        _ => (glyph, pixel_color(theme, fg)),
+    };
+    let bg = pixel_color(theme, bg);
+    let mut out = format!("{}{}{}", fg.fg(), bg.bg(), glyph);
+    if fg != ThemeColor::Default {
+        out.push_str(&color::Fg(color::Reset).to_string());
+    }
+    if bg != ThemeColor::Default {
+        out.push_str(&color::Bg(color::Reset).to_string());
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::theme::ThemePreset;
+
+    #[test]
+    fn test_cells() {
+        assert_eq!(RenderMode::Block.cells(64, 32), (64, 32));
+        assert_eq!(RenderMode::HalfBlock.cells(64, 32), (64, 16));
+        assert_eq!(RenderMode::Quadrant.cells(128, 64), (64, 32));
+        assert_eq!(RenderMode::Braille.cells(128, 64), (64, 16));
+    }
+
+    #[test]
+    fn test_glyphs() {
+        let theme = Theme::default();
+        // 4x4 display, lit pixels on the left column and the bottom row
+        #[rustfmt::skip]
+        let display = [
+            1, 0, 0, 0,
+            1, 0, 0, 0,
+            1, 0, 0, 0,
+            1, 1, 1, 1,
+        ];
+        assert_eq!(RenderMode::Block.glyph(&display, 4, 0, 0, &theme), "█");
+        assert_eq!(RenderMode::Block.glyph(&display, 4, 1, 0, &theme), " ");
+        assert_eq!(RenderMode::HalfBlock.glyph(&display, 4, 0, 1, &theme), "█");
+        assert_eq!(RenderMode::HalfBlock.glyph(&display, 4, 1, 1, &theme), "▄");
+        assert_eq!(RenderMode::Quadrant.glyph(&display, 4, 0, 0, &theme), "▌");
+        assert_eq!(RenderMode::Quadrant.glyph(&display, 4, 0, 1, &theme), "▙");
+        assert_eq!(RenderMode::Quadrant.glyph(&display, 4, 1, 1, &theme), "▄");
+        assert_eq!(RenderMode::Braille.glyph(&display, 4, 0, 0, &theme), "⣇");
+        assert_eq!(RenderMode::Braille.glyph(&display, 4, 1, 0, &theme), "⣀");
+
+        let theme = Theme::from(ThemePreset::Amber);
+        assert_eq!(
+            RenderMode::Block.glyph(&display, 4, 1, 0, &theme),
+            format!(
+                "{} {}",
+                color::Bg(color::Rgb(26, 15, 0)),
+                color::Bg(color::Reset)
+            )
+        );
+
+        let theme = Theme::default();
+        let display = [2, 1];
+        assert_eq!(
+            RenderMode::HalfBlock.glyph(&display, 1, 0, 0, &theme),
+            format!(
+                "{}▄{}",
+                color::Bg(color::Rgb(255, 102, 0)),
+                color::Bg(color::Reset)
+            )
+        );
+    }
+
+    #[test]
+    fn test_phosphor() {
+        let theme = Theme::default();
+        let mut phosphor = Phosphor::new(3);
+        assert_eq!(phosphor.apply(&[1, 2, 0]), vec![1, 2, 0]);
+        assert_eq!(phosphor.apply(&[0, 2, 0]), vec![3 << FADE_SHIFT, 2, 0]);
+        assert_eq!(
+            phosphor.apply(&[0, 0, 0]),
+            vec![2 << FADE_SHIFT, 3 << FADE_SHIFT, 0]
+        );
+        assert_eq!(
+            phosphor.apply(&[0, 0, 0]),
+            vec![1 << FADE_SHIFT, 2 << FADE_SHIFT, 0]
+        );
+        assert_eq!(phosphor.apply(&[1, 0, 0]), vec![1, 1 << FADE_SHIFT, 0]);
+        assert_eq!(phosphor.apply(&[1, 0, 0]), vec![1, 0, 0]);
+
+        assert_eq!(
+            RenderMode::Block.glyph(&[2 << FADE_SHIFT], 1, 0, 0, &theme),
+            "▒"
+        );
+
+        let mut phosphor = Phosphor::new(0);
+        phosphor.apply(&[1]);
+        assert_eq!(phosphor.apply(&[0]), vec![0]);
+    }
+}
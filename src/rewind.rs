@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+
+use crate::emulator::EmulatorState;
+use crate::timing::FRAME_RATE;
+
+/// A saved frame with its RAM left out, `undo` holds the bytes that differ
+/// in the previous frame's RAM
+struct Snapshot {
+    state: EmulatorState,
+    undo: Vec<(usize, u8)>,
+}
+
+/// Bounded history of emulator states, one per frame. Only the newest RAM is
+/// kept in full, older frames are rebuilt from per-frame deltas.
+pub struct Rewind {
+    capacity: usize,
+    snapshots: VecDeque<Snapshot>,
+    /// RAM of the newest snapshot
+    ram: Vec<u8>,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+            ram: Vec::new(),
+        }
+    }
+
+    /// Enough frames to go back `seconds` at 60 Hz
+    pub fn with_seconds(seconds: f64) -> Self {
+        Self::new((seconds * FRAME_RATE).round() as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn push(&mut self, state: &EmulatorState) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+            // Nothing is older than the oldest frame now
+            if let Some(oldest) = self.snapshots.front_mut() {
+                oldest.undo = Vec::new();
+            }
+        }
+
+        let mut state = state.clone();
+        let ram = std::mem::take(&mut state.ram);
+        let undo = if self.snapshots.is_empty() || self.ram.len() != ram.len() {
+            Vec::new()
+        } else {
+            self.ram
+                .iter()
+                .zip(ram.iter())
+                .enumerate()
+                .filter(|(_, (old, new))| old != new)
+                .map(|(addr, (old, _))| (addr, *old))
+                .collect()
+        };
+        self.ram = ram;
+        self.snapshots.push_back(Snapshot { state, undo });
+    }
+
+    /// Take the newest state off the history
+    pub fn pop(&mut self) -> Option<EmulatorState> {
+        let mut snapshot = self.snapshots.pop_back()?;
+        snapshot.state.ram = self.ram.clone();
+        for (addr, val) in snapshot.undo {
+            self.ram[addr] = val;
+        }
+        Some(snapshot.state)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    fn states() -> Vec<EmulatorState> {
+        let mut ch8 = Emulator::default();
+        (0..5u8)
+            .map(|i| {
+                let mut state = ch8.get_state().clone();
+                state.ram[0x300 + i as usize] = i + 1;
+                state.pc = 0x200 + 2 * i as u16;
+                ch8.load_state(&state).unwrap();
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rewind() {
+        let states = states();
+        let mut rewind = Rewind::new(10);
+        for state in states.iter() {
+            rewind.push(state);
+        }
+        assert_eq!(rewind.len(), 5);
+        for state in states.iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(state));
+        }
+        assert!(rewind.pop().is_none());
+    }
+
+    #[test]
+    fn test_rewind_capacity() {
+        let states = states();
+        let mut rewind = Rewind::new(3);
+        for state in states.iter() {
+            rewind.push(state);
+        }
+        assert_eq!(rewind.len(), 3);
+        for state in states[2..].iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(state));
+        }
+        assert!(rewind.is_empty());
+
+        let mut rewind = Rewind::new(0);
+        rewind.push(&states[0]);
+        assert!(rewind.pop().is_none());
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+
+use device_query::Keycode;
+use serde::Deserialize;
+
+use crate::movie::rom_hash;
+use crate::quirks::QuirksPreset;
+
+/// A program in the database, laid out like `programs.json` of the
+/// community chip-8-database so that file can be used as is. Fields this
+/// emulator has no use for are ignored.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    /// ROMs keyed by the SHA-1 of their bytes
+    roms: BTreeMap<String, Rom>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    /// Instructions per frame
+    tickrate: Option<u32>,
+    /// Hex keys used for each game control, such as `"up": 5`
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+}
+
+/// What the database knows about one ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    /// The first platform listed for the ROM
+    pub platform: Option<String>,
+    pub ipf: Option<u32>,
+    pub quirks: Option<QuirksPreset>,
+    /// Keys to bind to the hex keys the game's controls use
+    pub keys: Vec<(Keycode, u8)>,
+}
+
+pub struct RomDb {
+    programs: Vec<Program>,
+}
+
+impl RomDb {
+    pub fn load(fname: &str) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(fname)?).map_err(|err| format!("{}: {}", fname, err).into())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            programs: serde_json::from_str(text)?,
+        })
+    }
+
+    /// Entry for the program bytes as returned by `read_program`
+    pub fn lookup(&self, prog: &[u8]) -> Option<RomInfo> {
+        let hash = rom_hash(prog);
+        self.programs.iter().find_map(|program| {
+            let rom = program.roms.get(&hash)?;
+            let platform = rom.platforms.first().cloned();
+            Some(RomInfo {
+                title: program.title.clone(),
+                authors: program.authors.clone(),
+                quirks: platform.as_deref().and_then(platform_quirks),
+                platform,
+                ipf: rom.tickrate,
+                keys: rom
+                    .keys
+                    .iter()
+                    .filter_map(|(control, key)| Some((control_keycode(control)?, *key)))
+                    .filter(|(_, key)| *key < 16)
+                    .collect(),
+            })
+        })
+    }
+}
+
+/// Quirks preset for a chip-8-database platform id
+fn platform_quirks(platform: &str) -> Option<QuirksPreset> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some(QuirksPreset::Vip),
+        "modernChip8" => Some(QuirksPreset::Default),
+        "chip48" => Some(QuirksPreset::Chip48),
+        "superchip1" | "superchip" => Some(QuirksPreset::Schip),
+        "xochip" => Some(QuirksPreset::Xochip),
+        _ => None,
+    }
+}
+
+/// Key for a game control, player 2 keeps to the keypad
+fn control_keycode(control: &str) -> Option<Keycode> {
+    match control {
+        "up" => Some(Keycode::Up),
+        "down" => Some(Keycode::Down),
+        "left" => Some(Keycode::Left),
+        "right" => Some(Keycode::Right),
+        "a" => Some(Keycode::Space),
+        "b" => Some(Keycode::Enter),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let prog = [0x12, 0x00];
+        let db = format!(
+            r#"[
+                {{
+                    "title": "Other",
+                    "roms": {{ "0000000000000000000000000000000000000000": {{}} }}
+                }},
+                {{
+                    "title": "Loop",
+                    "authors": ["Someone"],
+                    "release": "2024",
+                    "roms": {{
+                        "{}": {{
+                            "file": "loop.ch8",
+                            "platforms": ["superchip", "xochip"],
+                            "tickrate": 30,
+                            "keys": {{ "left": 7, "a": 6, "player2Up": 1, "right": 16 }}
+                        }}
+                    }}
+                }}
+            ]"#,
+            rom_hash(&prog)
+        );
+        let db = RomDb::parse(&db).unwrap();
+        assert_eq!(
+            db.lookup(&prog),
+            Some(RomInfo {
+                title: "Loop".to_string(),
+                authors: vec!["Someone".to_string()],
+                platform: Some("superchip".to_string()),
+                ipf: Some(30),
+                quirks: Some(QuirksPreset::Schip),
+                keys: vec![(Keycode::Space, 6), (Keycode::Left, 7)],
+            })
+        );
+        assert_eq!(db.lookup(&[0x00, 0xE0]), None);
+
+        assert!(RomDb::parse("{}").is_err());
+    }
+}
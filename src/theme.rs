@@ -0,0 +1,282 @@
+use std::error::Error;
+use std::fs;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use termion::{color, style};
+
+/// A colour in a theme, written `default`, a 256 colour index such as `34`,
+/// or `#RRGGBB`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ThemeColor {
+    /// The terminal's own colour, or inverse video for highlights
+    Default,
+    Ansi(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl FromStr for ThemeColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid colour '{}', expected default, 0-255 or #RRGGBB", s);
+        if s == "default" {
+            return Ok(ThemeColor::Default);
+        }
+        if let Some(hex) = s.strip_prefix('#') {
+            let rgb = u32::from_str_radix(hex, 16).map_err(|_| err())?;
+            if hex.len() != 6 {
+                return Err(err());
+            }
+            return Ok(ThemeColor::Rgb(
+                (rgb >> 16) as u8,
+                (rgb >> 8) as u8,
+                rgb as u8,
+            ));
+        }
+        s.parse().map(ThemeColor::Ansi).map_err(|_| err())
+    }
+}
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl ThemeColor {
+    /// Escape code selecting this as the foreground colour
+    pub fn fg(self) -> String {
+        match self {
+            ThemeColor::Default => String::new(),
+            ThemeColor::Ansi(n) => color::Fg(color::AnsiValue(n)).to_string(),
+            ThemeColor::Rgb(r, g, b) => color::Fg(color::Rgb(r, g, b)).to_string(),
+        }
+    }
+
+    /// Escape code selecting this as the background colour
+    pub fn bg(self) -> String {
+        match self {
+            ThemeColor::Default => String::new(),
+            ThemeColor::Ansi(n) => color::Bg(color::AnsiValue(n)).to_string(),
+            ThemeColor::Rgb(r, g, b) => color::Bg(color::Rgb(r, g, b)).to_string(),
+        }
+    }
+
+    /// Escape code highlighting text with this background, `Default`
+    /// highlights by inverting
+    pub fn highlight(self) -> String {
+        match self {
+            ThemeColor::Default => style::Invert.to_string(),
+            _ => self.bg(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemePreset {
+    /// Terminal colours with inverse video highlights
+    #[default]
+    Default,
+    /// Green phosphor monitor
+    Green,
+    /// Amber phosphor monitor
+    Amber,
+    /// Greenish LCD with dark pixels
+    Lcd,
+}
+
+/// Colours used by the TUI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    /// Pixels lit on plane 1
+    pub pixel: ThemeColor,
+    /// Unlit pixels
+    pub background: ThemeColor,
+    /// XO-CHIP pixels lit on plane 2 only
+    pub plane2: ThemeColor,
+    /// XO-CHIP pixels lit on both planes
+    pub plane3: ThemeColor,
+    /// Text in the RAM pane
+    pub ram: ThemeColor,
+    /// Highlight of the word at the PC
+    pub pc: ThemeColor,
+    /// Highlight of breakpoints
+    pub breakpoint: ThemeColor,
+    /// Registers that changed since the last frame
+    pub changed: ThemeColor,
+    /// Highlight of held keys on the keypad
+    pub key: ThemeColor,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        ThemePreset::Default.into()
+    }
+}
+
+impl From<ThemePreset> for Theme {
+    fn from(preset: ThemePreset) -> Self {
+        use ThemeColor::*;
+        match preset {
+            ThemePreset::Default => Theme {
+                pixel: Default,
+                background: Default,
+                plane2: Rgb(255, 102, 0),
+                plane3: Rgb(102, 34, 0),
+                ram: Default,
+                pc: Default,
+                breakpoint: Ansi(1),
+                changed: Default,
+                key: Default,
+            },
+            ThemePreset::Green => Theme {
+                pixel: Rgb(51, 255, 102),
+                background: Rgb(0, 26, 10),
+                plane2: Rgb(26, 153, 51),
+                plane3: Rgb(13, 77, 26),
+                ram: Rgb(51, 204, 102),
+                pc: Rgb(20, 90, 42),
+                breakpoint: Rgb(128, 32, 32),
+                changed: Rgb(204, 255, 153),
+                key: Rgb(20, 90, 42),
+            },
+            ThemePreset::Amber => Theme {
+                pixel: Rgb(255, 176, 0),
+                background: Rgb(26, 15, 0),
+                plane2: Rgb(204, 122, 0),
+                plane3: Rgb(102, 61, 0),
+                ram: Rgb(255, 176, 0),
+                pc: Rgb(90, 62, 0),
+                breakpoint: Rgb(128, 32, 32),
+                changed: Rgb(255, 224, 128),
+                key: Rgb(90, 62, 0),
+            },
+            ThemePreset::Lcd => Theme {
+                pixel: Rgb(15, 56, 15),
+                background: Rgb(155, 188, 15),
+                plane2: Rgb(48, 98, 48),
+                plane3: Rgb(139, 172, 15),
+                ram: Default,
+                pc: Rgb(48, 98, 48),
+                breakpoint: Rgb(128, 32, 32),
+                changed: Rgb(139, 172, 15),
+                key: Rgb(48, 98, 48),
+            },
+        }
+    }
+}
+
+/// Theme file contents, every colour is optional and falls back to `base`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    base: Option<ThemePreset>,
+    pixel: Option<ThemeColor>,
+    background: Option<ThemeColor>,
+    plane2: Option<ThemeColor>,
+    plane3: Option<ThemeColor>,
+    ram: Option<ThemeColor>,
+    pc: Option<ThemeColor>,
+    breakpoint: Option<ThemeColor>,
+    changed: Option<ThemeColor>,
+    key: Option<ThemeColor>,
+}
+
+impl Theme {
+    /// Read a TOML theme file such as
+    ///
+    /// ```toml
+    /// base = "amber"
+    /// pixel = "#FFCC00"
+    /// pc = "94"
+    /// ```
+    ///
+    /// Colours that aren't given come from `base`, or from `preset` when the
+    /// file has no base.
+    pub fn load(fname: &str, preset: ThemePreset) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(fname)?, preset)
+            .map_err(|err| format!("{}: {}", fname, err).into())
+    }
+
+    pub fn parse(text: &str, preset: ThemePreset) -> Result<Self, Box<dyn Error>> {
+        let file: ThemeFile = toml::from_str(text)?;
+        let base = Theme::from(file.base.unwrap_or(preset));
+        Ok(Theme {
+            pixel: file.pixel.unwrap_or(base.pixel),
+            background: file.background.unwrap_or(base.background),
+            plane2: file.plane2.unwrap_or(base.plane2),
+            plane3: file.plane3.unwrap_or(base.plane3),
+            ram: file.ram.unwrap_or(base.ram),
+            pc: file.pc.unwrap_or(base.pc),
+            breakpoint: file.breakpoint.unwrap_or(base.breakpoint),
+            changed: file.changed.unwrap_or(base.changed),
+            key: file.key.unwrap_or(base.key),
+        })
+    }
+
+    /// Colour of a pixel fading out, `level` 3 is the brightest. Blends from
+    /// the pixel colour to the background when both are RGB, otherwise grays.
+    pub fn fade(&self, level: u8) -> ThemeColor {
+        match (self.pixel, self.background) {
+            (ThemeColor::Rgb(r1, g1, b1), ThemeColor::Rgb(r0, g0, b0)) => {
+                let mix = |from: u8, to: u8| {
+                    (from as i32 + (to as i32 - from as i32) * level as i32 / 4) as u8
+                };
+                ThemeColor::Rgb(mix(r0, r1), mix(g0, g1), mix(b0, b1))
+            }
+            _ => ThemeColor::Ansi(232 + 6 * level + 2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_theme_color() {
+        assert_eq!("default".parse(), Ok(ThemeColor::Default));
+        assert_eq!("34".parse(), Ok(ThemeColor::Ansi(34)));
+        assert_eq!("#FF8000".parse(), Ok(ThemeColor::Rgb(255, 128, 0)));
+        assert!("#FF80".parse::<ThemeColor>().is_err());
+        assert!("256".parse::<ThemeColor>().is_err());
+        assert!("red".parse::<ThemeColor>().is_err());
+    }
+
+    #[test]
+    fn test_theme_file() {
+        let theme = Theme::parse(
+            "base = \"amber\"\npixel = \"#FFCC00\"\npc = \"94\"\n",
+            ThemePreset::Green,
+        )
+        .unwrap();
+        assert_eq!(theme.pixel, ThemeColor::Rgb(255, 204, 0));
+        assert_eq!(theme.pc, ThemeColor::Ansi(94));
+        assert_eq!(theme.background, Theme::from(ThemePreset::Amber).background);
+
+        let theme = Theme::parse("key = \"default\"", ThemePreset::Green).unwrap();
+        assert_eq!(theme.key, ThemeColor::Default);
+        assert_eq!(theme.pixel, Theme::from(ThemePreset::Green).pixel);
+
+        assert!(Theme::parse("pixle = \"1\"", ThemePreset::Default).is_err());
+        assert!(Theme::parse("pixel = \"#12\"", ThemePreset::Default).is_err());
+    }
+
+    #[test]
+    fn test_fade() {
+        let theme = Theme::default();
+        assert_eq!(theme.fade(3), ThemeColor::Ansi(252));
+        let theme = Theme {
+            pixel: ThemeColor::Rgb(200, 100, 0),
+            background: ThemeColor::Rgb(0, 0, 40),
+            ..Theme::default()
+        };
+        assert_eq!(theme.fade(2), ThemeColor::Rgb(100, 50, 20));
+    }
+}
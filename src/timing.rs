@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+pub const FRAME_RATE: f64 = 60.;
+
+/// Most frames run back to back when catching up, beyond this the loop
+/// drops the backlog instead of fast-forwarding
+const MAX_CATCH_UP: u32 = 5;
+
+/// Hands out whole instructions per frame while carrying the fractional
+/// remainder, so e.g. 500 Hz runs exactly 500 instructions every 60 frames
+pub struct CycleBudget {
+    per_frame: f64,
+    carry: f64,
+}
+
+impl CycleBudget {
+    pub fn new(ips: f64) -> Self {
+        Self {
+            per_frame: ips / FRAME_RATE,
+            carry: 0.,
+        }
+    }
+
+    pub fn next_frame(&mut self) -> u64 {
+        self.carry += self.per_frame;
+        let n = self.carry.floor();
+        self.carry -= n;
+        n as u64
+    }
+}
+
+/// Fixed 60 Hz frame clock based on a monotonic deadline, sleep overshoot is
+/// absorbed by the next deadline instead of accumulating
+pub struct FrameClock {
+    period: Duration,
+    deadline: Instant,
+}
+
+impl FrameClock {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            period: Duration::from_secs_f64(1. / FRAME_RATE),
+            deadline: now,
+        }
+    }
+
+    /// Number of frames due at `now`, advancing the deadline past them
+    pub fn due(&mut self, now: Instant) -> u32 {
+        let mut frames = 0;
+        while self.deadline <= now && frames < MAX_CATCH_UP {
+            self.deadline += self.period;
+            frames += 1;
+        }
+        if self.deadline <= now {
+            self.deadline = now + self.period;
+        }
+
+        frames
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+/// Events per second measured over a sliding one second window
+pub struct RateMeter {
+    start: Instant,
+    count: u64,
+    rate: f64,
+}
+
+impl RateMeter {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            start: now,
+            count: 0,
+            rate: 0.,
+        }
+    }
+
+    pub fn add(&mut self, n: u64, now: Instant) {
+        self.count += n;
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        if elapsed >= 1. {
+            self.rate = self.count as f64 / elapsed;
+            self.count = 0;
+            self.start = now;
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cycle_budget() {
+        let mut budget = CycleBudget::new(500.);
+        let total: u64 = (0..60).map(|_| budget.next_frame()).sum();
+        assert_eq!(total, 500);
+
+        let mut budget = CycleBudget::new(600.);
+        assert!((0..60).all(|_| budget.next_frame() == 10));
+    }
+
+    #[test]
+    fn test_frame_clock() {
+        let start = Instant::now();
+        let mut clock = FrameClock::new(start);
+        assert_eq!(clock.due(start), 1);
+        assert_eq!(clock.due(start), 0);
+
+        // Half a second behind is capped and the backlog dropped
+        let late = start + Duration::from_millis(500);
+        assert_eq!(clock.due(late), MAX_CATCH_UP);
+        assert!(clock.deadline() > late);
+    }
+}
@@ -0,0 +1,287 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+use clap::ValueEnum;
+
+use crate::debugger::parse_addr;
+use crate::disasm::{decode_at, Instruction};
+use crate::emulator::{Emulator, StepOutcome};
+use crate::ops::{Ops, Src};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OpClass {
+    /// Jumps, calls, returns, skips and exit
+    Flow,
+    /// Register loads and arithmetic, including random
+    Alu,
+    /// Instructions that set I or access RAM
+    Memory,
+    /// Drawing, scrolling, resolution and plane selection
+    Display,
+    /// Keypad reads and skips
+    Input,
+    /// Delay, sound timer and pitch
+    Timer,
+}
+
+impl OpClass {
+    fn of(op: &Ops) -> Self {
+        match op {
+            Ops::CallSubRoutine(_)
+            | Ops::ReturnSubRoutine
+            | Ops::Jump(_)
+            | Ops::JumpRelative(_)
+            | Ops::Exit => OpClass::Flow,
+            Ops::JumpEq(Src::Key, _) | Ops::JumpNeq(Src::Key, _) => OpClass::Input,
+            Ops::JumpEq(_, _) | Ops::JumpNeq(_, _) => OpClass::Flow,
+            Ops::GetKey(_) => OpClass::Input,
+            Ops::Add(Src::IReg, _, _) => OpClass::Memory,
+            Ops::Add(_, _, _)
+            | Ops::Sub(_, _, _)
+            | Ops::And(_, _, _)
+            | Ops::Or(_, _, _)
+            | Ops::Xor(_, _, _)
+            | Ops::LShift(_, _)
+            | Ops::RShift(_, _)
+            | Ops::Rand(_, _)
+            | Ops::FlagsDump(_)
+            | Ops::FlagsLoad(_) => OpClass::Alu,
+            Ops::GetSprite(_)
+            | Ops::GetBigSprite(_)
+            | Ops::BCD(_)
+            | Ops::RegDump(_)
+            | Ops::RegLoad(_)
+            | Ops::RangeDump(_, _)
+            | Ops::RangeLoad(_, _)
+            | Ops::LongLoad(_)
+            | Ops::LoadAudio => OpClass::Memory,
+            Ops::DisplayClear
+            | Ops::DisplayUpdate(_, _, _)
+            | Ops::ScrollDown(_)
+            | Ops::ScrollUp(_)
+            | Ops::ScrollRight
+            | Ops::ScrollLeft
+            | Ops::LowRes
+            | Ops::HighRes
+            | Ops::SelectPlanes(_) => OpClass::Display,
+            Ops::ReadDelay(_) | Ops::WriteDelay(_) | Ops::WriteSound(_) | Ops::SetPitch(_) => {
+                OpClass::Timer
+            }
+        }
+    }
+}
+
+/// Inclusive address range written `<start>-<end>` in hex
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddrRange(pub u16, pub u16);
+
+impl FromStr for AddrRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected <start>-<end>, found '{}'", s))?;
+        let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+        if end < start {
+            return Err(format!("empty address range '{}'", s));
+        }
+        Ok(AddrRange(start, end))
+    }
+}
+
+/// Which instructions end up in the trace, everything by default
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub range: Option<AddrRange>,
+    /// Only these opcode classes, or all when empty
+    pub classes: Vec<OpClass>,
+}
+
+impl TraceFilter {
+    fn matches(&self, instr: &Instruction) -> bool {
+        let in_range = self
+            .range
+            .is_none_or(|AddrRange(start, end)| (start..=end).contains(&instr.addr));
+        let in_class = self.classes.is_empty()
+            || instr
+                .op()
+                .is_some_and(|op| self.classes.contains(&OpClass::of(&op)));
+        in_range && in_class
+    }
+}
+
+/// Writes one line per executed instruction:
+///
+/// ```text
+///        1 0200 00E0     CLS                    I:0000 V:00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 DT:00 ST:00 D:D2063DC5
+/// ```
+///
+/// The cycle count, PC and raw opcode bytes, the Cowgod mnemonic, then I,
+/// V0-VF, the timers and a hash of the display after the instruction.
+/// Cycles count every executed instruction, so filtered traces keep the
+/// numbering of a full one.
+pub struct Tracer<W: Write> {
+    out: W,
+    filter: TraceFilter,
+    cycles: u64,
+    pending: Option<Instruction>,
+    /// The exit instruction has been logged, later steps execute nothing
+    exited: bool,
+    error: Option<io::Error>,
+}
+
+impl Tracer<BufWriter<File>> {
+    pub fn create(fname: &str, filter: TraceFilter) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(fname)?), filter))
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W, filter: TraceFilter) -> Self {
+        Self {
+            out,
+            filter,
+            cycles: 0,
+            pending: None,
+            exited: false,
+            error: None,
+        }
+    }
+
+    /// Decode the instruction about to run, before it can modify itself
+    pub fn before_step(&mut self, ch8: &Emulator) {
+        let state = ch8.get_state();
+        let pc = state.pc as usize;
+        let end = (pc + 4).min(state.ram.len());
+        self.pending = state
+            .ram
+            .get(pc..end)
+            .filter(|bytes| !bytes.is_empty())
+            .map(|bytes| decode_at(bytes, state.pc, 0).with_quirks(ch8.get_quirks()));
+    }
+
+    pub fn after_step(&mut self, ch8: &Emulator, outcome: StepOutcome) {
+        let instr = match (self.pending.take(), outcome) {
+            (Some(instr), StepOutcome::Executed) => instr,
+            (Some(instr), StepOutcome::Exited) if !self.exited => {
+                self.exited = true;
+                instr
+            }
+            _ => return,
+        };
+        self.cycles += 1;
+        if self.error.is_some() || !self.filter.matches(&instr) {
+            return;
+        }
+
+        let state = ch8.get_state();
+        let raw: String = instr.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let regs: Vec<String> = state
+            .register_bank
+            .iter()
+            .map(|v| format!("{:02X}", v))
+            .collect();
+        let res = writeln!(
+            self.out,
+            "{:>8} {:04X} {:<8} {:<22} I:{:04X} V:{} DT:{:02X} ST:{:02X} D:{:08X}",
+            self.cycles,
+            instr.addr,
+            raw,
+            instr.to_string(),
+            state.ireg,
+            regs.join(" "),
+            state.delay_timer,
+            state.sound_timer,
+            display_hash(&state.display)
+        );
+        if let Err(err) = res {
+            self.error = Some(err);
+        }
+    }
+
+    /// Flush the trace, reporting the first write error if any
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// 32 bit FNV-1a hash of the display, so traces can tell when drawing differs
+pub fn display_hash(display: &[u8]) -> u32 {
+    display.iter().fold(0x811C9DC5, |hash, px| {
+        (hash ^ (*px as u32)).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn trace(filter: TraceFilter) -> Vec<String> {
+        let mut ch8 = Emulator::default();
+        ch8.load_prog(
+            &assemble(
+                "
+                : main
+                    v0 := 0x12
+                    i := 0x300
+                    sub
+                    exit
+                : sub
+                    v0 += 1
+                    return
+                ",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let mut tracer = Tracer::new(Vec::new(), filter);
+        loop {
+            tracer.before_step(&ch8);
+            let outcome = ch8.step(0).unwrap();
+            tracer.after_step(&ch8, outcome);
+            if outcome == StepOutcome::Exited {
+                break;
+            }
+        }
+
+        let out = String::from_utf8(tracer.finish().unwrap()).unwrap();
+        out.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_trace_format() {
+        let lines = trace(TraceFilter::default());
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[0],
+            "       1 0200 6012     LD V0, 0x12            I:0000 \
+             V:12 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 DT:00 ST:00 D:D2063DC5"
+        );
+        assert!(lines[3].starts_with("       4 0208 7001     ADD V0, 0x01           I:0300 V:13"));
+    }
+
+    #[test]
+    fn test_trace_filters() {
+        let lines = trace(TraceFilter {
+            range: Some("208-20B".parse().unwrap()),
+            classes: Vec::new(),
+        });
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("       4 0208"));
+
+        let lines = trace(TraceFilter {
+            range: None,
+            classes: vec![OpClass::Flow],
+        });
+        let pcs: Vec<&str> = lines.iter().map(|l| &l[9..13]).collect();
+        assert_eq!(pcs, vec!["0204", "020A", "0206"]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::error::Error;
+use std::fs;
+
+/// One parsed `--trace` line
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TraceLine<'a> {
+    text: &'a str,
+    cycle: u64,
+    /// Named values in the order they appear: PC, opcode, I, V0-VF, DT, ST, display
+    fields: Vec<(String, &'a str)>,
+}
+
+/// First point where two traces disagree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the line in both traces
+    pub line: usize,
+    pub cycle: u64,
+    /// Names of the differing fields, e.g. `PC`, `V3`, `I`, `DT` or `display`
+    pub fields: Vec<String>,
+}
+
+fn parse_line(text: &str) -> Option<TraceLine<'_>> {
+    let mut tokens = text.split_whitespace();
+    let cycle = tokens.next()?.parse().ok()?;
+    let pc = tokens.next()?;
+    let opcode = tokens.next()?;
+
+    // The mnemonic has spaces in it, the state starts at the I register
+    let state = &text[text.find(" I:")? + 1..];
+    let mut fields = vec![(String::from("PC"), pc), (String::from("opcode"), opcode)];
+    let mut regs = 0;
+    for token in state.split_whitespace() {
+        let (name, val) = match token.split_once(':') {
+            Some(("I", val)) => (String::from("I"), val),
+            Some(("V", val)) => (String::from("V0"), val),
+            Some(("DT", val)) => (String::from("DT"), val),
+            Some(("ST", val)) => (String::from("ST"), val),
+            Some(("D", val)) => (String::from("display"), val),
+            Some(_) => return None,
+            None => {
+                regs += 1;
+                (format!("V{:X}", regs), token)
+            }
+        };
+        fields.push((name, val));
+    }
+
+    Some(TraceLine {
+        text,
+        cycle,
+        fields,
+    })
+}
+
+fn parse_trace<'a>(name: &str, trace: &'a str) -> Result<Vec<TraceLine<'a>>, String> {
+    trace
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            parse_line(line).ok_or_else(|| format!("{} line {}: malformed trace line", name, i + 1))
+        })
+        .collect()
+}
+
+fn compare(a: &[TraceLine], b: &[TraceLine]) -> Option<Divergence> {
+    for (i, (la, lb)) in a.iter().zip(b.iter()).enumerate() {
+        if la.cycle != lb.cycle {
+            return Some(Divergence {
+                line: i,
+                cycle: la.cycle.min(lb.cycle),
+                fields: vec![String::from("cycle")],
+            });
+        }
+
+        let mut fields: Vec<String> = la
+            .fields
+            .iter()
+            .zip(lb.fields.iter())
+            .filter(|((na, va), (nb, vb))| na != nb || va != vb)
+            .map(|((name, _), _)| name.clone())
+            .collect();
+        if la.fields.len() != lb.fields.len() && fields.is_empty() {
+            fields.push(String::from("format"));
+        }
+        if !fields.is_empty() {
+            return Some(Divergence {
+                line: i,
+                cycle: la.cycle,
+                fields,
+            });
+        }
+    }
+
+    // One trace stopping early is a divergence too
+    let (longer, shorter) = if a.len() > b.len() { (a, b) } else { (b, a) };
+    longer.get(shorter.len()).map(|line| Divergence {
+        line: shorter.len(),
+        cycle: line.cycle,
+        fields: vec![String::from("end of trace")],
+    })
+}
+
+/// Find the first line where two traces differ
+pub fn first_divergence(a: &str, b: &str) -> Result<Option<Divergence>, String> {
+    Ok(compare(&parse_trace("a", a)?, &parse_trace("b", b)?))
+}
+
+/// Compare two trace files and print the first divergence with `context`
+/// lines around it. Diverging traces are reported as an error.
+pub fn run(fname_a: &str, fname_b: &str, context: usize) -> Result<(), Box<dyn Error>> {
+    let (text_a, text_b) = (fs::read_to_string(fname_a)?, fs::read_to_string(fname_b)?);
+    let a = parse_trace(fname_a, &text_a)?;
+    let b = parse_trace(fname_b, &text_b)?;
+
+    match compare(&a, &b) {
+        Some(div) => {
+            print!("{}", report(&div, (fname_a, &a), (fname_b, &b), context));
+            Err(format!("traces diverge at cycle {}", div.cycle).into())
+        }
+        None => {
+            println!("Traces match ({} instructions)", a.len());
+            Ok(())
+        }
+    }
+}
+
+/// The divergence with the matching lines before it, then the differing
+/// line and up to `context` lines after it from each trace, diff style
+fn report(
+    div: &Divergence,
+    a: (&str, &[TraceLine]),
+    b: (&str, &[TraceLine]),
+    context: usize,
+) -> String {
+    let mut out = format!(
+        "First divergence at cycle {}: {}\n",
+        div.cycle,
+        div.fields.join(", ")
+    );
+    for line in a.1[div.line.saturating_sub(context)..div.line].iter() {
+        out.push_str(&format!("  {}\n", line.text));
+    }
+    for (marker, (name, trace)) in [('-', a), ('+', b)] {
+        match trace.get(div.line..) {
+            Some(rest) if !rest.is_empty() => {
+                for line in rest.iter().take(context + 1) {
+                    out.push_str(&format!("{} {}\n", marker, line.text));
+                }
+            }
+            _ => out.push_str(&format!("{} end of {}\n", marker, name)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const A: &str = "\
+       1 0200 6012     LD V0, 0x12            I:0000 V:12 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 DT:00 ST:00 D:D2063DC5
+       2 0202 A300     LD I, 0x300            I:0300 V:12 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 DT:00 ST:00 D:D2063DC5
+       3 0204 8016     SHR V0, V1             I:0300 V:09 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 DT:00 ST:00 D:D2063DC5
+";
+
+    #[test]
+    fn test_identical_traces() {
+        assert_eq!(first_divergence(A, A), Ok(None));
+    }
+
+    #[test]
+    fn test_divergence() {
+        // Shift quirk: VY shifted into VX instead of VX in place
+        let b = A.replace(
+            "V:09 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "V:00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+        );
+        let div = first_divergence(A, &b).unwrap().unwrap();
+        assert_eq!((div.line, div.cycle), (2, 3));
+        assert_eq!(div.fields, vec!["V0"]);
+
+        let b = A.replace(
+            "V:12 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 DT:00",
+            "V:12 00 00 00 00 00 00 00 00 00 00 00 00 00 00 01 DT:00",
+        );
+        assert_eq!(first_divergence(A, &b).unwrap().unwrap().fields, vec!["VF"]);
+
+        let b: String = A.lines().take(2).map(|l| format!("{}\n", l)).collect();
+        let div = first_divergence(A, &b).unwrap().unwrap();
+        assert_eq!(div.fields, vec!["end of trace"]);
+        assert_eq!(div.cycle, 3);
+
+        assert!(first_divergence(A, "garbage").is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        let b = A.replace(
+            "LD I, 0x300            I:0300",
+            "LD I, 0x300            I:0301",
+        );
+        let (a, b) = (parse_trace("a", A).unwrap(), parse_trace("b", &b).unwrap());
+        let div = compare(&a, &b).unwrap();
+        let out = report(&div, ("a", &a), ("b", &b), 1);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "First divergence at cycle 2: I");
+        assert_eq!(lines[1], format!("  {}", a[0].text));
+        assert_eq!(lines[2], format!("- {}", a[1].text));
+        assert_eq!(lines[3], format!("- {}", a[2].text));
+        assert_eq!(lines[4], format!("+ {}", b[1].text));
+        assert_eq!(lines[5], format!("+ {}", b[2].text));
+        assert_eq!(lines.len(), 6);
+
+        let out = report(&div, ("a", &a), ("b", &b[..1]), 0);
+        assert!(out.ends_with("+ end of b\n"));
+    }
+}